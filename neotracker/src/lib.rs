@@ -5,6 +5,18 @@
 #![no_std]
 #![deny(missing_docs)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+extern crate std;
+
+mod mixer;
+mod renderer;
+
+pub use mixer::{Mixer, MixerChannel};
+pub use renderer::{Measurement, OutputSample, RenderEvent, Renderer};
+
 /// The ways in which parsing can fail
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Error {
@@ -12,37 +24,421 @@ pub enum Error {
     FileTooSmall,
     /// The file did not contain a recognised magic value
     WrongMagicValue,
+    /// A song order was empty, too long, or referred to a pattern which
+    /// does not exist
+    InvalidOrder,
+    /// The sizes given in the file do not fit together, so the patterns run
+    /// past the end of the file
+    InconsistentLayout,
+    /// The file ends part way through the sample data
+    TruncatedSampleData,
+    /// A module cannot hold any more samples
+    TooManySamples,
+    /// A sample has more data than a module can hold
+    SampleTooLong,
+    /// A module cannot hold any more patterns
+    TooManyPatterns,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::FileTooSmall => write!(f, "file is too small to be a module"),
+            Error::WrongMagicValue => write!(f, "file is not a recognised kind of module"),
+            Error::InvalidOrder => write!(f, "song order is not valid"),
+            Error::InconsistentLayout => write!(f, "module layout is not consistent"),
+            Error::TruncatedSampleData => write!(f, "sample data is cut short"),
+            Error::TooManySamples => write!(f, "module has no room for another sample"),
+            Error::SampleTooLong => write!(f, "sample is too long"),
+            Error::TooManyPatterns => write!(f, "module has no room for another pattern"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// Why a module could not be loaded, in more detail than [`Error`].
+///
+/// Generated by [`ProTrackerModule::new_verbose`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DetailedError {
+    /// The file was too small.
+    ///
+    /// Once the format is known, `need` is the size of its header and one
+    /// pattern. If the file is too short to hold a 31 sample header, `need`
+    /// is the size of the smallest `M.K.` module, 2108 bytes. That is only a
+    /// lower bound for 31 sample modules - the file could instead be the
+    /// start of a 15 sample Soundtracker module, which can be as small as
+    /// 1624 bytes.
+    TooSmall {
+        /// How many bytes the file has
+        have: usize,
+        /// How many bytes it needs, at least
+        need: usize,
+    },
+    /// The file did not contain a recognised magic value
+    WrongMagicValue {
+        /// The four bytes where the magic value should be
+        found: [u8; 4],
+    },
+    /// An entry in the order table names a pattern that no module can hold
+    InvalidOrder {
+        /// Where in the order table the entry is
+        position: u8,
+        /// The pattern it names
+        pattern: u8,
+    },
+}
+
+impl From<DetailedError> for Error {
+    fn from(error: DetailedError) -> Error {
+        match error {
+            DetailedError::TooSmall { .. } => Error::FileTooSmall,
+            DetailedError::WrongMagicValue { .. } => Error::WrongMagicValue,
+            DetailedError::InvalidOrder { .. } => Error::InvalidOrder,
+        }
+    }
+}
+
+impl core::fmt::Display for DetailedError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DetailedError::TooSmall { have, need } => write!(
+                f,
+                "file is too small to be a module ({} bytes, needs at least {})",
+                have, need
+            ),
+            DetailedError::WrongMagicValue { found } => write!(
+                f,
+                "file is not a recognised kind of module (found {:02x?})",
+                found
+            ),
+            DetailedError::InvalidOrder { position, pattern } => write!(
+                f,
+                "song order is not valid (position {} names pattern {})",
+                position, pattern
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DetailedError {}
+
+/// The most channels a module can have
+pub const MAX_CHANNELS: usize = 32;
+
+/// The rate at which a PAL Amiga's Paula chip counts down periods, in Hz.
+///
+/// Dividing this by a period gives the rate, in bytes per second, at which
+/// sample data is played.
+pub const PAL_CLOCK: u32 = 3_546_895;
+
+/// The rate at which an NTSC Amiga's Paula chip counts down periods, in Hz.
+///
+/// This is the clock that gives the well known 8363 Hz for a C-2.
+pub const NTSC_CLOCK: u32 = 3_579_545;
+
+/// The kinds of module file we recognise
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// A four channel Pro Tracker module, tagged `M.K.`, `M!K!` or `FLT4`
+    ProTracker4,
+    /// A module with some other number of channels, tagged `6CHN`, `8CHN`,
+    /// `FLT8`, `16CH` and so on
+    MultiChannel(u8),
+    /// An older four channel module with only 15 samples, as made by
+    /// Ultimate Soundtracker. These have no signature.
+    SoundTracker15,
+}
+
+impl Format {
+    /// Work out the format from the four byte signature
+    fn from_magic(magic: &[u8]) -> Option<Format> {
+        let channels = match magic {
+            b"M.K." | b"M!K!" | b"FLT4" => 4,
+            // Startrekker's FLT8 actually stores each pattern as two four
+            // channel halves, which we do not yet untangle
+            b"FLT8" => 8,
+            [digit @ b'1'..=b'9', b'C', b'H', b'N'] => digit - b'0',
+            [tens @ b'0'..=b'9', units @ b'0'..=b'9', b'C', b'H' | b'N'] => {
+                (tens - b'0') * 10 + (units - b'0')
+            }
+            _ => return None,
+        };
+        match channels {
+            4 => Some(Format::ProTracker4),
+            1..=32 => Some(Format::MultiChannel(channels)),
+            _ => None,
+        }
+    }
+
+    /// How many channels this kind of module has
+    pub const fn channel_count(&self) -> usize {
+        match self {
+            Format::ProTracker4 | Format::SoundTracker15 => 4,
+            Format::MultiChannel(n) => *n as usize,
+        }
+    }
+}
+
+/// The trackers whose effect commands we can tell apart
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Dialect {
+    /// Pro Tracker, or something compatible with it
+    ProTracker,
+    /// Noise Tracker, which lacks many of Pro Tracker's effects
+    NoiseTracker,
+}
+
+/// Work out what kind of module this is, without parsing it.
+///
+/// Mostly only the signature is examined, so this is very cheap, but a file
+/// that passes may still be rejected by [`ProTrackerModule::new`]. If there
+/// is no signature, we check whether the header makes sense as a 15 sample
+/// Soundtracker module. Returns `None` if this does not look like a module
+/// we recognise.
+pub fn probe(data: &[u8]) -> Option<Format> {
+    if let Some(format) = data
+        .get(ProTrackerModule::MK_RANGE)
+        .and_then(Format::from_magic)
+    {
+        Some(format)
+    } else if ProTrackerModule::looks_like_soundtracker(data) {
+        Some(Format::SoundTracker15)
+    } else {
+        None
+    }
+}
+
+/// Play a whole module, and write the audio out as a stereo 16-bit WAV file.
+///
+/// Any problem with the module is reported as an error of kind
+/// [`std::io::ErrorKind::InvalidData`], wrapping an [`Error`].
+#[cfg(feature = "std")]
+pub fn render_to_wav_file(
+    data: &[u8],
+    sample_rate: u32,
+    path: &std::path::Path,
+) -> std::io::Result<()> {
+    use std::io::{Seek, Write};
+    let modfile = ProTrackerModule::new(data)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let mut renderer = Renderer::new(modfile, sample_rate);
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    // We don't know how long the song is yet, so we fix up the header later
+    file.write_all(&wav_header(2, 16, sample_rate, 0))?;
+    let mut data_len: u32 = 0;
+    while !renderer.finished() {
+        let (left, right) = renderer.next_sample();
+        file.write_all(&left.to_le_bytes())?;
+        file.write_all(&right.to_le_bytes())?;
+        data_len = data_len.saturating_add(4);
+    }
+    file.rewind()?;
+    file.write_all(&wav_header(2, 16, sample_rate, data_len))?;
+    file.flush()
+}
+
+/// The 44 byte header of a PCM WAV file, with the data chunk to follow.
+#[cfg(feature = "alloc")]
+fn wav_header(channels: u16, bits: u16, sample_rate: u32, data_len: u32) -> [u8; 44] {
+    let block_align = channels * (bits / 8);
+    // Chunks must be an even number of bytes long, so there may be padding
+    let riff_len = 36 + data_len + (data_len & 1);
+    let mut header = [0u8; 44];
+    header[0..4].copy_from_slice(b"RIFF");
+    header[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    header[8..12].copy_from_slice(b"WAVE");
+    header[12..16].copy_from_slice(b"fmt ");
+    header[16..20].copy_from_slice(&16u32.to_le_bytes());
+    // PCM
+    header[20..22].copy_from_slice(&1u16.to_le_bytes());
+    header[22..24].copy_from_slice(&channels.to_le_bytes());
+    header[24..28].copy_from_slice(&sample_rate.to_le_bytes());
+    header[28..32].copy_from_slice(&(sample_rate * u32::from(block_align)).to_le_bytes());
+    header[32..34].copy_from_slice(&block_align.to_le_bytes());
+    header[34..36].copy_from_slice(&bits.to_le_bytes());
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&data_len.to_le_bytes());
+    header
 }
 
 /// Represents a Pro Tracker Module.
 ///
 /// Stores no data - just holds a &[u8] containing the raw file contents.
+#[derive(Clone)]
 pub struct ProTrackerModule<'a> {
     data: &'a [u8],
+    /// What kind of module this is
+    format: Format,
 }
 
 impl<'a> ProTrackerModule<'a> {
-    const MINIMUM_LENGTH: usize = 1084 + 1024;
-    const SONG_LENGTH_OFFSET: usize = 950;
-    const SONG_POSITIONS_RANGE: core::ops::Range<usize> = 952..1080;
+    const HEADER_LENGTH: usize = 1084;
     const MK_RANGE: core::ops::Range<usize> = 1080..1084;
-    const MK_MAGIC: [u8; 4] = [b'M', b'.', b'K', b'.'];
+    /// The size of the order table
+    const SONG_POSITIONS_LEN: usize = 128;
+    /// The most patterns a module can hold
+    const MAX_PATTERNS: u8 = 128;
+    /// The header of a 15 sample module, with no signature
+    const SOUNDTRACKER_HEADER_LENGTH: usize = 600;
 
     /// Create a wrapper around a MOD file already in memory.
     ///
     /// Does some basic checks to ensure it looks like a MOD file.
     pub fn new(data: &'a [u8]) -> Result<ProTrackerModule<'a>, Error> {
-        if data.len() < Self::MINIMUM_LENGTH {
-            return Err(Error::FileTooSmall);
+        Self::new_verbose(data).map_err(Error::from)
+    }
+
+    /// Create a wrapper around a MOD file already in memory, saying exactly
+    /// what is wrong if it cannot be loaded.
+    ///
+    /// Does the same checks as [`ProTrackerModule::new`].
+    pub fn new_verbose(data: &'a [u8]) -> Result<ProTrackerModule<'a>, DetailedError> {
+        if data.len() < Self::HEADER_LENGTH {
+            return Err(DetailedError::TooSmall {
+                have: data.len(),
+                need: Self::HEADER_LENGTH + Pattern::PATTERN_LEN_4CH,
+            });
+        }
+        let Some(format) = probe(data) else {
+            let mut found = [0u8; 4];
+            found.copy_from_slice(&data[Self::MK_RANGE]);
+            return Err(DetailedError::WrongMagicValue { found });
+        };
+        let module = ProTrackerModule { data, format };
+        // No module holds more than 128 patterns, and a higher pattern
+        // number would overflow our count of them
+        if let Some((position, pattern)) = data[module.song_positions_range()]
+            .iter()
+            .enumerate()
+            .find(|(_, pattern)| **pattern >= Self::MAX_PATTERNS)
+        {
+            return Err(DetailedError::InvalidOrder {
+                position: position as u8,
+                pattern: *pattern,
+            });
+        }
+        // There must be at least one pattern
+        let need = module.pattern_offset() + module.pattern_len();
+        if data.len() < need {
+            return Err(DetailedError::TooSmall {
+                have: data.len(),
+                need,
+            });
+        }
+        Ok(module)
+    }
+
+    /// Read a whole MOD file from something like a file, or an entry in an
+    /// archive, into an [`OwnedModule`].
+    ///
+    /// A `ProTrackerModule` only borrows its data, so it cannot be returned
+    /// here. Use [`OwnedModule::module`] to borrow one from the result. This
+    /// is the same as [`OwnedModule::from_reader`].
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<OwnedModule> {
+        OwnedModule::from_reader(reader)
+    }
+
+    /// Wrap data which has already been checked by [`ProTrackerModule::new`].
+    #[cfg(feature = "alloc")]
+    fn new_prechecked(data: &'a [u8]) -> ProTrackerModule<'a> {
+        let format = probe(data).unwrap_or(Format::ProTracker4);
+        ProTrackerModule { data, format }
+    }
+
+    /// Does this look like a 15 sample Soundtracker module?
+    ///
+    /// These have no signature, so we check that the header makes sense
+    /// instead: a printable title, sensible sample volumes, a song of 1 to
+    /// 128 positions using patterns 0 to 63, and enough data for all of
+    /// those patterns.
+    fn looks_like_soundtracker(data: &[u8]) -> bool {
+        let song_length_offset =
+            SampleInfo::SAMPLE_INFO_OFFSET + (15 * SampleInfo::SAMPLE_INFO_LEN);
+        let Some(header) = data.get(0..Self::SOUNDTRACKER_HEADER_LENGTH) else {
+            return false;
+        };
+        let title_ok = header[0..SampleInfo::SAMPLE_INFO_OFFSET]
+            .iter()
+            .all(|b| *b == 0 || (b' '..=b'~').contains(b));
+        // Soundtracker has no finetune, so that byte is always zero
+        let samples_ok = header[SampleInfo::SAMPLE_INFO_OFFSET..song_length_offset]
+            .chunks_exact(SampleInfo::SAMPLE_INFO_LEN)
+            .all(|sample| sample[24] == 0 && sample[25] <= 64);
+        let song_length = header[song_length_offset];
+        let order = &header[song_length_offset + 2..];
+        let highest = order.iter().cloned().max().unwrap_or(0);
+        title_ok
+            && samples_ok
+            && (1..=128).contains(&song_length)
+            && highest < 64
+            && data.len() >= header.len() + (usize::from(highest) + 1) * Pattern::PATTERN_LEN_4CH
+    }
+
+    /// How many channels each pattern has
+    pub fn channel_count(&self) -> usize {
+        self.format.channel_count()
+    }
+
+    /// What kind of module this is
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// How many slots there are in the sample table.
+    ///
+    /// This is 15 for an old Soundtracker module, and 31 for everything
+    /// else.
+    pub fn sample_count(&self) -> u8 {
+        match self.format {
+            Format::SoundTracker15 => 15,
+            Format::ProTracker4 | Format::MultiChannel(_) => 31,
+        }
+    }
+
+    /// Where the song length lives, just after the sample table
+    fn song_length_offset(&self) -> usize {
+        SampleInfo::SAMPLE_INFO_OFFSET
+            + (usize::from(self.sample_count()) * SampleInfo::SAMPLE_INFO_LEN)
+    }
+
+    /// Where the order table lives
+    fn song_positions_range(&self) -> core::ops::Range<usize> {
+        let start = self.song_length_offset() + 2;
+        start..start + Self::SONG_POSITIONS_LEN
+    }
+
+    /// Where the signature lives. Empty if the format has no signature.
+    fn magic_range(&self) -> core::ops::Range<usize> {
+        let start = self.song_positions_range().end;
+        match self.format {
+            Format::SoundTracker15 => start..start,
+            Format::ProTracker4 | Format::MultiChannel(_) => start..start + 4,
         }
-        if data[Self::MK_RANGE] != Self::MK_MAGIC {
-            return Err(Error::WrongMagicValue);
+    }
+
+    /// Where the first pattern starts
+    fn pattern_offset(&self) -> usize {
+        self.magic_range().end
+    }
+
+    /// The title of the song, as a byte slice.
+    ///
+    /// Is probably not UTF-8 encoded.
+    pub fn song_name(&self) -> &[u8] {
+        let mut name: &[u8] = &self.data[0..SampleInfo::SAMPLE_INFO_OFFSET];
+        while let Some(trimmed_name) = name.strip_suffix(b"\0") {
+            name = trimmed_name;
         }
-        Ok(ProTrackerModule { data })
+        name
     }
 
     /// Iterate through all the samples
-    pub fn samples(&self) -> SampleIter {
+    pub fn samples(&self) -> SampleIter<'_> {
         SampleIter {
             parent: self,
             sample_no: 1,
@@ -56,7 +452,7 @@ impl<'a> ProTrackerModule<'a> {
     ///
     /// Requires a walk through all the samples so we can
     /// get the start of the sample data.
-    pub fn sample(&self, sample_no: u8) -> Option<Sample> {
+    pub fn sample(&self, sample_no: u8) -> Option<Sample<'_>> {
         if sample_no == 0 {
             None
         } else {
@@ -67,19 +463,32 @@ impl<'a> ProTrackerModule<'a> {
 
     /// Get metadata for a specific sample
     ///
-    /// Can do a direct access, but it won't return correct sample data.
-    pub fn sample_info(&self, sample_no: u8) -> Option<Sample> {
-        if (1..=31).contains(&sample_no) {
-            // this value is wrong, but we did warn them it would be
-            Some(Sample::new(sample_no, self.sample_offset(), self))
+    /// Unlike [`ProTrackerModule::sample`], this does not need to walk the
+    /// sample table to find where the PCM data lives - so it is quicker, but
+    /// cannot give you the PCM data.
+    pub fn sample_info(&self, sample_no: u8) -> Option<SampleInfo<'_>> {
+        if (1..=self.sample_count()).contains(&sample_no) {
+            Some(SampleInfo::new(sample_no, self))
         } else {
             None
         }
     }
 
     /// Number patterns that make up the song.
+    ///
+    /// The order table only has room for 128 positions, so a larger value
+    /// in the file is treated as 128.
     pub fn song_length(&self) -> u8 {
-        self.data[Self::SONG_LENGTH_OFFSET]
+        self.data[self.song_length_offset()].min(Self::SONG_POSITIONS_LEN as u8)
+    }
+
+    /// The byte after the song length (byte 951 in a 31 sample module).
+    ///
+    /// Pro Tracker always writes 127 here. Noise Tracker, and some other
+    /// trackers, store the song position to loop back to when the song
+    /// ends.
+    pub fn restart_position(&self) -> u8 {
+        self.data[self.song_length_offset() + 1]
     }
 
     /// Which pattern should be played at this song position
@@ -93,17 +502,46 @@ impl<'a> ProTrackerModule<'a> {
     /// Get the list of all the patterns in the song.
     pub fn song_positions(&self) -> &[u8] {
         let length = usize::from(self.song_length());
-        &self.data[Self::SONG_POSITIONS_RANGE][0..length]
+        &self.data[self.song_positions_range()][0..length]
+    }
+
+    /// Get each pattern in the song, in the order they are played.
+    ///
+    /// Song positions which name a pattern that is not in the file are
+    /// skipped. Position Jump and Pattern Break effects are ignored.
+    pub fn song_patterns(&self) -> impl Iterator<Item = Pattern<'_>> + '_ {
+        self.song_positions()
+            .iter()
+            .filter_map(|pattern_no| self.pattern(*pattern_no))
     }
 
     /// Return the number of patterns in the file
+    ///
+    /// The same as [`ProTrackerModule::stored_pattern_count`].
     pub fn num_patterns(&self) -> u8 {
-        *self.data[Self::SONG_POSITIONS_RANGE].iter().max().unwrap() + 1
+        self.stored_pattern_count()
+    }
+
+    /// How many patterns are stored in the file.
+    ///
+    /// The file does not record this directly. Pro Tracker stores every
+    /// pattern up to the highest one named anywhere in the 128 entry order
+    /// table - including entries past the end of the song, which some tools
+    /// use to keep patterns that are not played. So this can be more than
+    /// one plus the highest pattern in
+    /// [`ProTrackerModule::song_positions`], and it is this number which
+    /// tells us where the sample data starts.
+    pub fn stored_pattern_count(&self) -> u8 {
+        *self.data[self.song_positions_range()].iter().max().unwrap() + 1
     }
 
     /// Get info on a specific pattern
-    pub fn pattern(&self, pattern_no: u8) -> Option<Pattern> {
-        if pattern_no < self.num_patterns() {
+    ///
+    /// Returns `None` if the pattern does not exist, or if the file has been
+    /// cut short and does not contain all of it.
+    pub fn pattern(&self, pattern_no: u8) -> Option<Pattern<'_>> {
+        let end = self.pattern_offset() + (usize::from(pattern_no) + 1) * self.pattern_len();
+        if pattern_no < self.num_patterns() && end <= self.data.len() {
             Some(Pattern {
                 pattern_no,
                 parent: self,
@@ -113,9 +551,524 @@ impl<'a> ProTrackerModule<'a> {
         }
     }
 
+    /// Find the sample slots which are not in use.
+    ///
+    /// A slot is free if its sample has no data and no name (a name of just
+    /// spaces counts as no name). Yields 1-based sample numbers.
+    pub fn free_sample_slots(&self) -> impl Iterator<Item = u8> + '_ {
+        self.samples()
+            .filter(|s| s.sample_length == 0 && s.name().iter().all(|b| *b == 0 || *b == b' '))
+            .map(|s| s.sample_no)
+    }
+
+    /// Find the patterns which are stored in the file but never played.
+    ///
+    /// Yields each pattern number below
+    /// [`ProTrackerModule::stored_pattern_count`] which is not in
+    /// [`ProTrackerModule::song_positions`], in order.
+    pub fn unused_patterns(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.stored_pattern_count())
+            .filter(|pattern_no| !self.song_positions().contains(pattern_no))
+    }
+
+    /// How much memory the sample data takes up, in bytes.
+    ///
+    /// This is the total of the lengths given in the sample headers, which
+    /// is not the same as the size of the file.
+    pub fn total_sample_bytes(&self) -> usize {
+        self.samples().map(|s| s.sample_length_bytes()).sum()
+    }
+
+    /// Count how many times each effect, with each argument, is used.
+    ///
+    /// Every stored pattern is checked, whether it is in the song or not.
+    /// Effects are given in the order they are first found.
+    #[cfg(feature = "alloc")]
+    pub fn effect_usage(&self) -> impl Iterator<Item = (Effect, u32)> {
+        let mut usage: alloc::vec::Vec<(Effect, u32)> = alloc::vec::Vec::new();
+        for pattern_no in 0..self.num_patterns() {
+            let Some(pattern) = self.pattern(pattern_no) else {
+                continue;
+            };
+            for line in pattern.lines() {
+                for effect in line.channels().iter().filter_map(|note| note.effect()) {
+                    match usage.iter_mut().find(|(e, _)| *e == effect) {
+                        Some((_, count)) => *count += 1,
+                        None => usage.push((effect, 1)),
+                    }
+                }
+            }
+        }
+        usage.into_iter()
+    }
+
+    /// Find samples which contain identical PCM data.
+    ///
+    /// Returns pairs of 1-based sample numbers, lowest first. Empty samples
+    /// are not compared.
+    #[cfg(feature = "alloc")]
+    pub fn duplicate_samples(&self) -> alloc::vec::Vec<(u8, u8)> {
+        let mut duplicates = alloc::vec::Vec::new();
+        for first in self.samples() {
+            let first_data = first.raw_sample_bytes();
+            if first_data.is_empty() {
+                continue;
+            }
+            for second in self.samples().skip(usize::from(first.sample_no)) {
+                if first_data == second.raw_sample_bytes() {
+                    duplicates.push((first.sample_no, second.sample_no));
+                }
+            }
+        }
+        duplicates
+    }
+
+    /// Find the song positions which play the given sample.
+    ///
+    /// Yields each song position once, in order, if any note in the pattern
+    /// at that position uses the 1-based `sample_no`.
+    pub fn positions_using_sample(&self, sample_no: u8) -> impl Iterator<Item = u8> + '_ {
+        self.song_positions()
+            .iter()
+            .enumerate()
+            .filter_map(move |(position, pattern_no)| {
+                let pattern = self.pattern(*pattern_no)?;
+                let mask = 1u32.checked_shl(u32::from(sample_no)).unwrap_or(0);
+                let used = sample_no != 0 && pattern.used_samples() & mask != 0;
+                used.then_some(position as u8)
+            })
+    }
+
+    /// Reduce the song to a single melody line, for playing on a buzzer.
+    ///
+    /// Yields one item per line played: how many ticks the line lasts, and
+    /// the frequency of the loudest note started on that line (if any). A
+    /// period of 428 (C-2) is taken to be middle C. Where two notes are
+    /// equally loud, the lowest channel wins.
+    ///
+    /// Every line of every pattern in the order list is played - Position
+    /// Jump and Pattern Break effects are ignored.
+    pub fn monophonic_events(&self) -> impl Iterator<Item = (u8, Option<f32>)> + '_ {
+        const MIDDLE_C_HZ: f32 = 261.63;
+        self.song_positions()
+            .iter()
+            .flat_map(|pattern_no| (0..64).map(move |row| (*pattern_no, row)))
+            .scan(6, move |ticks_per_line, (pattern_no, row)| {
+                let line = self.pattern(pattern_no)?.line(row)?;
+                let mut loudest: Option<(u8, u16)> = None;
+                for note in line.channels().iter() {
+                    match note.effect() {
+                        Some(Effect::SetSpeed(value)) if (1..=31).contains(&value) => {
+                            *ticks_per_line = value;
+                        }
+                        _ => {}
+                    }
+                    if note.period() == 0 {
+                        continue;
+                    }
+                    let volume = match note.effect() {
+                        Some(Effect::SetVolume(value)) => value,
+                        _ => self
+                            .sample(note.sample_no())
+                            .map(|s| s.volume())
+                            .unwrap_or(64),
+                    };
+                    if loudest.is_none_or(|(loudest_volume, _)| volume > loudest_volume) {
+                        loudest = Some((volume, note.period()));
+                    }
+                }
+                let frequency = loudest.map(|(_, period)| MIDDLE_C_HZ * 428.0 / f32::from(period));
+                Some((*ticks_per_line, frequency))
+            })
+    }
+
+    /// Estimate how long the song plays for, in seconds, without rendering
+    /// it.
+    ///
+    /// Starts at `initial_speed` ticks per line and `initial_bpm` beats per
+    /// minute (6 and 125 are the usual defaults), and follows any Set Speed
+    /// effects, with each tick lasting `2.5 / bpm` seconds. Position Jump
+    /// and Pattern Break effects are followed too, but this is only an
+    /// estimate: the song is taken to end as soon as it goes back to a
+    /// position it has already played, so a `Bxx` or `Dxx` loop is only
+    /// counted once.
+    pub fn duration_secs(&self, initial_speed: u8, initial_bpm: u8) -> f32 {
+        let mut speed = f32::from(initial_speed.max(1));
+        let mut bpm = f32::from(initial_bpm.max(1));
+        let mut seconds = 0.0;
+        let mut visited = 0u128;
+        let mut position = 0u8;
+        let mut start_line = 0u8;
+        while position < self.song_length() && visited & (1 << position) == 0 {
+            visited |= 1 << position;
+            let Some(pattern) = self.song_position(position).and_then(|p| self.pattern(p)) else {
+                break;
+            };
+            let mut next = (position + 1, 0);
+            for line in (start_line..64).filter_map(|row| pattern.line(row)) {
+                let mut jumped = false;
+                for note in line.channels().iter() {
+                    match note.effect() {
+                        Some(Effect::SetSpeed(value)) if (1..=31).contains(&value) => {
+                            speed = f32::from(value);
+                        }
+                        Some(Effect::SetSpeed(value)) if value > 31 => {
+                            bpm = f32::from(value);
+                        }
+                        Some(Effect::PositionJump(value)) => {
+                            next.0 = value;
+                            jumped = true;
+                        }
+                        Some(Effect::PatternBreak(row)) => {
+                            // The row is given as two decimal digits
+                            next.1 = (row >> 4) * 10 + (row & 0x0F);
+                            jumped = true;
+                        }
+                        _ => {}
+                    }
+                }
+                seconds += speed * 2.5 / bpm;
+                if jumped {
+                    break;
+                }
+            }
+            (position, start_line) = next;
+        }
+        seconds
+    }
+
+    /// Render the start of the song, as interleaved stereo samples (left,
+    /// then right).
+    ///
+    /// Stops after `seconds` of audio, or at the end of the song if that
+    /// comes first. Handy for making a short preview of a module.
+    #[cfg(feature = "alloc")]
+    pub fn render_preview(&self, sample_rate: u32, seconds: f32) -> alloc::vec::Vec<i16> {
+        let max_frames = (sample_rate as f32 * seconds) as usize;
+        let mut renderer = Renderer::new(self.clone(), sample_rate);
+        let mut output = alloc::vec::Vec::new();
+        let mut frames = 0;
+        while frames < max_frames && !renderer.finished() {
+            let (left, right) = renderer.next_sample();
+            output.push(left);
+            output.push(right);
+            frames += 1;
+        }
+        output
+    }
+
+    /// Build a new module, containing just the given pattern and the samples
+    /// it uses.
+    ///
+    /// The song plays the pattern once. Samples keep their numbers, and
+    /// every other sample slot is left empty. The new module always has 31
+    /// sample slots, so a 15 sample Soundtracker module comes out as a Pro
+    /// Tracker one. Returns `None` if the pattern does not exist.
+    #[cfg(feature = "alloc")]
+    pub fn extract_pattern_module(&self, pattern_no: u8) -> Option<OwnedModule> {
+        let pattern = self.pattern(pattern_no)?;
+        let used_samples = pattern.used_samples();
+        let mut data = alloc::vec::Vec::new();
+        data.extend_from_slice(&self.data[0..SampleInfo::SAMPLE_INFO_OFFSET]);
+        for sample_no in 1..=31 {
+            match self.sample_info(sample_no) {
+                Some(info) if used_samples & (1 << sample_no) != 0 => {
+                    data.extend_from_slice(info.metadata_bytes());
+                }
+                _ => data.extend_from_slice(&SampleInfo::EMPTY_HEADER),
+            }
+        }
+        data.extend_from_slice(&[1, 127]);
+        data.extend_from_slice(&[0; 128]);
+        match self.format {
+            Format::SoundTracker15 => data.extend_from_slice(b"M.K."),
+            Format::ProTracker4 | Format::MultiChannel(_) => {
+                data.extend_from_slice(&self.data[self.magic_range()])
+            }
+        }
+        data.extend_from_slice(pattern.metadata_bytes());
+        for sample in self.samples() {
+            if used_samples & (1 << sample.sample_no) != 0 {
+                data.extend_from_slice(&sample.stored_pcm());
+            }
+        }
+        OwnedModule::new(data.into_boxed_slice()).ok()
+    }
+
+    /// Write the module out again with [`ModuleBuilder`], from its parsed
+    /// samples, patterns and song order.
+    ///
+    /// A module saved by Pro Tracker comes back byte-for-byte the same, which
+    /// shows that reading and writing agree. Some things are not carried
+    /// over, so the bytes differ if the original has:
+    ///
+    /// * anything after the sample data (see
+    ///   [`ProTrackerModule::trailing_data`]),
+    /// * a restart position other than the 127 that Pro Tracker writes,
+    /// * entries in the order table past the end of the song - only the
+    ///   highest pattern number is kept, in the last entry,
+    /// * a tag other than the `M.K.` or `M!K!` that Pro Tracker would use,
+    /// * a sample volume over 64, or anything in the top four bits of a
+    ///   finetune byte,
+    /// * patterns or sample data cut short by the end of the file, which are
+    ///   filled out with zeros.
+    ///
+    /// A 15 sample Soundtracker module comes out as a 31 sample Pro Tracker
+    /// one. Returns `None` if the module does not have four channels, as
+    /// [`ModuleBuilder`] only writes four channel modules, or if the song has
+    /// no positions.
+    #[cfg(feature = "alloc")]
+    pub fn rebuild(&self) -> Option<OwnedModule> {
+        if self.channel_count() != 4 {
+            return None;
+        }
+        let mut builder = ModuleBuilder::new();
+        builder.set_title(&self.data[0..SampleInfo::SAMPLE_INFO_OFFSET]);
+        for sample in self.samples() {
+            builder
+                .add_sample(
+                    sample.name(),
+                    &sample.stored_pcm(),
+                    sample.volume(),
+                    sample.finetune(),
+                    sample.repeat_point(),
+                    sample.repeat_length(),
+                )
+                .ok()?;
+        }
+        for pattern_no in 0..self.stored_pattern_count() {
+            let lines = match self.pattern(pattern_no) {
+                Some(pattern) => pattern.to_array(),
+                None => [[Note::new(0, 0, None); 4]; 64],
+            };
+            builder.add_pattern(&lines).ok()?;
+        }
+        builder.set_positions(self.song_positions()).ok()?;
+        OwnedModule::new(builder.build().into_boxed_slice()).ok()
+    }
+
+    /// Turn the song into a list of notes, each with a start time and a
+    /// duration.
+    ///
+    /// A note lasts until the next note on the same channel, or until the
+    /// channel's volume is set to zero, or until the song ends. As with
+    /// [`ProTrackerModule::monophonic_events`], every line of every pattern
+    /// in the order list is played, ignoring jumps and breaks. The notes are
+    /// produced in the order that they end.
+    pub fn note_events(&self) -> NoteEventIter<'_> {
+        NoteEventIter {
+            parent: self,
+            position: 0,
+            row: 0,
+            tick: 0,
+            ticks_per_line: 6,
+            playing: [None; MAX_CHANNELS],
+            ended: [None; MAX_CHANNELS],
+        }
+    }
+
+    /// How many notes are started in each block of `bucket_rows` lines, as
+    /// the song plays.
+    ///
+    /// As with [`ProTrackerModule::monophonic_events`], every line of every
+    /// pattern in the order list is counted, ignoring jumps and breaks. The
+    /// last bucket may cover fewer lines than the others. A `bucket_rows`
+    /// of zero is treated as one.
+    #[cfg(feature = "alloc")]
+    pub fn density_timeline(&self, bucket_rows: u32) -> alloc::vec::Vec<u32> {
+        let bucket_rows = bucket_rows.max(1);
+        let mut timeline = alloc::vec::Vec::new();
+        let mut rows = 0;
+        for pattern in self.song_patterns() {
+            for line in pattern.lines() {
+                if rows % bucket_rows == 0 {
+                    timeline.push(0);
+                }
+                let notes = line.channels().iter().filter(|n| n.period() != 0).count();
+                if let Some(bucket) = timeline.last_mut() {
+                    *bucket += notes as u32;
+                }
+                rows += 1;
+            }
+        }
+        timeline
+    }
+
+    /// The most notes started on any one line of the song.
+    ///
+    /// Only patterns in the song order are checked. A note is started
+    /// wherever a pattern cell has a period.
+    pub fn max_simultaneous_notes(&self) -> u8 {
+        let mut max = 0;
+        for pattern in self.song_patterns() {
+            for line in pattern.lines() {
+                let notes = line.channels().iter().filter(|n| n.period() != 0).count();
+                max = max.max(notes as u8);
+            }
+        }
+        max
+    }
+
+    /// For each channel, the pattern with the most non-empty cells on that
+    /// channel - which is often where the main riff lives.
+    ///
+    /// Every stored pattern is checked, whether it is in the song order or
+    /// not, and if two patterns tie the lower numbered one wins. Channels
+    /// which are always empty give `None`. Only the first four channels are
+    /// reported.
+    pub fn busiest_pattern_per_channel(&self) -> [Option<u8>; 4] {
+        let mut busiest: [Option<(u8, usize)>; 4] = [None; 4];
+        for pattern_no in 0..self.num_patterns() {
+            let Some(pattern) = self.pattern(pattern_no) else {
+                continue;
+            };
+            let mut counts = [0; 4];
+            for line in pattern.lines() {
+                for (count, note) in counts.iter_mut().zip(line.channels()) {
+                    if !note.is_empty() {
+                        *count += 1;
+                    }
+                }
+            }
+            for (best, count) in busiest.iter_mut().zip(counts) {
+                if count > 0 && best.is_none_or(|(_, best_count)| count > best_count) {
+                    *best = Some((pattern_no, count));
+                }
+            }
+        }
+        busiest.map(|best| best.map(|(pattern_no, _)| pattern_no))
+    }
+
+    /// Is this a short jingle or sound effect, rather than a full song?
+    ///
+    /// We say a module is short if its song is no more than two positions
+    /// long, and plays for under 30 seconds. The playing time is an
+    /// estimate - see [`ProTrackerModule::duration_secs`] for what is taken
+    /// into account.
+    pub fn is_short(&self) -> bool {
+        const MAX_POSITIONS: u8 = 2;
+        const MAX_SECONDS: f32 = 30.0;
+        self.song_length() <= MAX_POSITIONS && self.duration_secs(6, 125) < MAX_SECONDS
+    }
+
+    /// Make a best-effort guess at which tracker wrote this module.
+    ///
+    /// If any pattern uses an effect that only Pro Tracker has (commands 5,
+    /// 6, 7, 9 and E, or setting the tempo in beats per minute), we say Pro
+    /// Tracker. Otherwise we look at the byte after the song length: Pro
+    /// Tracker always writes 127 there, while Noise Tracker stores a restart
+    /// position. Modules that are ambiguous are assumed to be Pro Tracker.
+    pub fn likely_dialect(&self) -> Dialect {
+        for pattern_no in 0..self.num_patterns() {
+            let Some(pattern) = self.pattern(pattern_no) else {
+                continue;
+            };
+            for line in pattern.lines() {
+                for note in line.channels().iter() {
+                    let command = note.effect_u16() >> 8;
+                    let arg = note.effect_u16() & 0xFF;
+                    if matches!(command, 5 | 6 | 7 | 9 | 14) || (command == 15 && arg >= 0x20) {
+                        return Dialect::ProTracker;
+                    }
+                }
+            }
+        }
+        if self.restart_position() < 127 {
+            Dialect::NoiseTracker
+        } else {
+            Dialect::ProTracker
+        }
+    }
+
+    /// Any bytes in the file after the end of the last sample.
+    ///
+    /// Some editors store extra information here. Most files have none.
+    pub fn trailing_data(&self) -> &[u8] {
+        let end = self.sample_offset()
+            + self
+                .samples()
+                .map(|s| s.sample_length_bytes())
+                .sum::<usize>();
+        self.data.get(end..).unwrap_or(&[])
+    }
+
+    /// Any text the author left after the sample data.
+    ///
+    /// Trailing NUL padding is ignored. Returns `None` if there is no trailing
+    /// data, or if it is anything other than printable ASCII (plus tabs and
+    /// line breaks).
+    pub fn comment(&self) -> Option<&[u8]> {
+        let mut text = self.trailing_data();
+        while let [rest @ .., 0] = text {
+            text = rest;
+        }
+        let printable = |b: &u8| matches!(b, b' '..=b'~' | b'\t' | b'\r' | b'\n');
+        if !text.is_empty() && text.iter().all(printable) {
+            Some(text)
+        } else {
+            None
+        }
+    }
+
+    /// Check that the parts of the module fit together.
+    ///
+    /// [`ProTrackerModule::new`] only checks the header and the first
+    /// pattern. This also checks that every pattern is in the file, and that
+    /// the file holds all of the sample data (so it has not been cut short,
+    /// say by a partial download). The accessors cope with broken files
+    /// either way, returning `None` or clipping data to the end of the file,
+    /// but this lets you reject them up front.
+    ///
+    /// A sample loop which runs past the end of its sample is not an error.
+    /// Plenty of real modules have them, and the [`Renderer`] cuts the loop
+    /// short at the end of the sample.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.sample_offset() > self.data.len() {
+            return Err(Error::InconsistentLayout);
+        }
+        if self.sample_offset() + self.total_sample_bytes() > self.data.len() {
+            return Err(Error::TruncatedSampleData);
+        }
+        Ok(())
+    }
+
+    /// Where each part of the module lives in the file.
+    ///
+    /// Ranges are clipped to the end of the file, so a truncated module gives
+    /// short (or empty) ranges for the parts that are missing.
+    pub fn layout(&self) -> Layout {
+        let clip = |range: core::ops::Range<usize>| {
+            range.start.min(self.data.len())..range.end.min(self.data.len())
+        };
+        let mut samples: [core::ops::Range<usize>; 31] = Default::default();
+        for (range, sample) in samples.iter_mut().zip(self.samples()) {
+            *range = clip(sample.file_offset..sample.file_offset + sample.sample_length_bytes());
+        }
+        Layout {
+            title: 0..SampleInfo::SAMPLE_INFO_OFFSET,
+            sample_headers: SampleInfo::SAMPLE_INFO_OFFSET..self.song_length_offset(),
+            song_length: self.song_length_offset()..self.song_positions_range().start,
+            order_list: self.song_positions_range(),
+            magic: self.magic_range(),
+            patterns: clip(self.pattern_offset()..self.sample_offset()),
+            samples,
+        }
+    }
+
+    /// The PCM data for a sample, borrowed for as long as the file is.
+    pub(crate) fn sample_pcm(&self, sample: &Sample) -> &'a [u8] {
+        &self.data[sample.pcm_range()]
+    }
+
     /// Where in the file do the samples start?
     fn sample_offset(&self) -> usize {
-        Pattern::PATTERN_INFO_OFFSET + (usize::from(self.num_patterns()) * Pattern::PATTERN_LEN)
+        self.pattern_offset() + (usize::from(self.stored_pattern_count()) * self.pattern_len())
+    }
+
+    /// How many bytes each pattern takes up
+    fn pattern_len(&self) -> usize {
+        // 64 lines, each with four bytes per channel
+        64 * 4 * self.channel_count()
     }
 }
 
@@ -130,26 +1083,403 @@ impl<'a> core::fmt::Debug for ProTrackerModule<'a> {
     }
 }
 
+/// The byte ranges which make up a module file.
+///
+/// Generated by [`ProTrackerModule::layout()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// The song title
+    pub title: core::ops::Range<usize>,
+    /// The name, length, finetune, volume and loop points of every sample -
+    /// 31 of them, or 15 for [`Format::SoundTracker15`]
+    pub sample_headers: core::ops::Range<usize>,
+    /// The song length, and the restart byte
+    pub song_length: core::ops::Range<usize>,
+    /// Which pattern to play at each song position
+    pub order_list: core::ops::Range<usize>,
+    /// The format signature, e.g. `M.K.`. This is an empty range for
+    /// [`Format::SoundTracker15`], which has no signature.
+    pub magic: core::ops::Range<usize>,
+    /// All the pattern data
+    pub patterns: core::ops::Range<usize>,
+    /// The PCM data for samples 1 to 31. A [`Format::SoundTracker15`]
+    /// module only has 15 samples, so samples 16 to 31 are given as the
+    /// empty range `0..0`.
+    pub samples: [core::ops::Range<usize>; 31],
+}
+
+/// A Pro Tracker Module which owns its file contents.
+///
+/// Useful when the module needs to outlive the buffer it was loaded into, or
+/// be shared between threads. The contents can never be modified once
+/// checked, so an `OwnedModule` is `Send` and `Sync`, and can be put in an
+/// `Arc` and read from any number of threads at once.
+#[cfg(feature = "alloc")]
+pub struct OwnedModule {
+    data: alloc::boxed::Box<[u8]>,
+}
+
+#[cfg(feature = "alloc")]
+impl OwnedModule {
+    /// Take ownership of a MOD file already in memory.
+    ///
+    /// Does the same checks as [`ProTrackerModule::new`].
+    pub fn new(data: alloc::boxed::Box<[u8]>) -> Result<OwnedModule, Error> {
+        ProTrackerModule::new(&data)?;
+        Ok(OwnedModule { data })
+    }
+
+    /// Read a whole MOD file from something like a file, or an entry in an
+    /// archive.
+    ///
+    /// If the data is not a valid module, the error is of kind
+    /// [`std::io::ErrorKind::InvalidData`] and wraps an [`Error`].
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: std::io::Read>(mut reader: R) -> std::io::Result<OwnedModule> {
+        let mut data = alloc::vec::Vec::new();
+        reader.read_to_end(&mut data)?;
+        OwnedModule::new(data.into_boxed_slice())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Borrow the module, to inspect it.
+    pub fn module(&self) -> ProTrackerModule<'_> {
+        ProTrackerModule::new_prechecked(&self.data)
+    }
+
+    /// The raw file contents
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Replace the song order - the list of patterns to play.
+    ///
+    /// The order must have between 1 and 128 entries, and may only refer to
+    /// patterns already in the file. The song length is updated to match.
+    ///
+    /// The file does not record how many patterns it holds - readers take
+    /// the highest pattern number in the whole order table. If the new order
+    /// no longer uses the highest pattern, it is kept in an unused slot at
+    /// the end of the table so the sample data can still be found. That
+    /// needs a spare slot, so an order of 128 entries must use the highest
+    /// pattern.
+    pub fn set_order(&mut self, order: &[u8]) -> Result<(), Error> {
+        let module = self.module();
+        let num_patterns = module.num_patterns();
+        let song_length_offset = module.song_length_offset();
+        let song_positions_range = module.song_positions_range();
+        if order.is_empty()
+            || order.len() > song_positions_range.len()
+            || order.iter().any(|pattern_no| *pattern_no >= num_patterns)
+        {
+            return Err(Error::InvalidOrder);
+        }
+        let highest = num_patterns - 1;
+        let keep_highest = !order.contains(&highest);
+        if keep_highest && order.len() == song_positions_range.len() {
+            return Err(Error::InvalidOrder);
+        }
+        let table = &mut self.data[song_positions_range];
+        table.fill(0);
+        table[0..order.len()].copy_from_slice(order);
+        if keep_highest {
+            table[table.len() - 1] = highest;
+        }
+        self.data[song_length_offset] = order.len() as u8;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl core::fmt::Debug for OwnedModule {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("OwnedModule").field(&self.module()).finish()
+    }
+}
+
+/// Puts together a new four channel `M.K.` (or `M!K!`) module, from code.
+///
+/// Samples and patterns are numbered in the order they are added, starting
+/// from sample 1 and pattern 0. Any pattern the song refers to which has
+/// not been added is left empty.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Default)]
+pub struct ModuleBuilder {
+    title: [u8; 20],
+    samples: alloc::vec::Vec<([u8; SampleInfo::SAMPLE_INFO_LEN], alloc::vec::Vec<u8>)>,
+    positions: alloc::vec::Vec<u8>,
+    patterns: alloc::vec::Vec<[u8; Pattern::PATTERN_LEN_4CH]>,
+}
+
+#[cfg(feature = "alloc")]
+impl ModuleBuilder {
+    /// The most PCM data a sample can hold, in bytes
+    const MAX_SAMPLE_LEN: usize = 0xFFFF * 2;
+
+    /// Start an empty module, with no title, no samples and a song which
+    /// plays pattern 0 once.
+    pub fn new() -> ModuleBuilder {
+        ModuleBuilder::default()
+    }
+
+    /// Set the song title. Only the first 20 bytes are kept.
+    pub fn set_title(&mut self, title: &[u8]) -> &mut Self {
+        let len = title.len().min(self.title.len());
+        self.title = [0; 20];
+        self.title[0..len].copy_from_slice(&title[0..len]);
+        self
+    }
+
+    /// Add a sample, returning its sample number.
+    ///
+    /// Only the first 22 bytes of the name are kept. The `data` is signed
+    /// 8-bit PCM, and is padded with a zero if it is an odd number of bytes
+    /// long. The `repeat_point` and `repeat_length` are in 16-bit words, as
+    /// in the file, and a `repeat_length` of 0 or 1 means the sample does
+    /// not loop.
+    ///
+    /// Fails with [`Error::TooManySamples`] if all 31 samples have been
+    /// added already, or [`Error::SampleTooLong`] if the data is more than
+    /// 131070 bytes long.
+    pub fn add_sample(
+        &mut self,
+        name: &[u8],
+        data: &[u8],
+        volume: u8,
+        finetune: i8,
+        repeat_point: u16,
+        repeat_length: u16,
+    ) -> Result<u8, Error> {
+        if self.samples.len() >= 31 {
+            return Err(Error::TooManySamples);
+        }
+        if data.len() > Self::MAX_SAMPLE_LEN {
+            return Err(Error::SampleTooLong);
+        }
+        let mut pcm = data.to_vec();
+        if pcm.len() & 1 != 0 {
+            pcm.push(0);
+        }
+        let mut header = [0u8; SampleInfo::SAMPLE_INFO_LEN];
+        let name_len = name.len().min(22);
+        header[0..name_len].copy_from_slice(&name[0..name_len]);
+        header[22..24].copy_from_slice(&((pcm.len() / 2) as u16).to_be_bytes());
+        header[24] = finetune.clamp(-8, 7) as u8 & 0x0F;
+        header[25] = volume.min(64);
+        header[26..28].copy_from_slice(&repeat_point.to_be_bytes());
+        header[28..30].copy_from_slice(&repeat_length.to_be_bytes());
+        self.samples.push((header, pcm));
+        Ok(self.samples.len() as u8)
+    }
+
+    /// Set the song order - the list of patterns to play.
+    ///
+    /// The order must have between 1 and 128 entries, and each pattern
+    /// number must be less than 128.
+    pub fn set_positions(&mut self, positions: &[u8]) -> Result<&mut Self, Error> {
+        if positions.is_empty() || positions.len() > 128 || positions.iter().any(|p| *p >= 128) {
+            return Err(Error::InvalidOrder);
+        }
+        self.positions = positions.to_vec();
+        Ok(self)
+    }
+
+    /// Add a pattern of 64 lines, returning its pattern number.
+    ///
+    /// Fails with [`Error::TooManyPatterns`] if 128 patterns have been added
+    /// already.
+    pub fn add_pattern(&mut self, lines: &[[Note; 4]; 64]) -> Result<u8, Error> {
+        if self.patterns.len() >= 128 {
+            return Err(Error::TooManyPatterns);
+        }
+        let mut pattern = [0u8; Pattern::PATTERN_LEN_4CH];
+        for (chunk, note) in pattern.chunks_exact_mut(4).zip(lines.iter().flatten()) {
+            chunk.copy_from_slice(&note.data);
+        }
+        self.patterns.push(pattern);
+        Ok((self.patterns.len() - 1) as u8)
+    }
+
+    /// Lay out the module as the contents of a MOD file.
+    ///
+    /// As with Pro Tracker, a module with more than 64 patterns is tagged
+    /// `M!K!` rather than `M.K.`.
+    pub fn build(&self) -> alloc::vec::Vec<u8> {
+        let positions: &[u8] = if self.positions.is_empty() {
+            &[0]
+        } else {
+            &self.positions
+        };
+        let mut order = [0u8; ProTrackerModule::SONG_POSITIONS_LEN];
+        order[0..positions.len()].copy_from_slice(positions);
+        let referenced = positions
+            .iter()
+            .map(|p| usize::from(*p) + 1)
+            .max()
+            .unwrap_or(1);
+        let mut num_patterns = referenced.max(self.patterns.len());
+        if num_patterns > referenced {
+            // Readers count the patterns from the highest number in the
+            // whole table, so an unplayed pattern on the end goes in a spare
+            // slot. Without one, the unplayed patterns are left out.
+            if positions.len() < order.len() {
+                order[order.len() - 1] = (num_patterns - 1) as u8;
+            } else {
+                num_patterns = referenced;
+            }
+        }
+        let mut data = alloc::vec::Vec::new();
+        data.extend_from_slice(&self.title);
+        for sample_idx in 0..31 {
+            match self.samples.get(sample_idx) {
+                Some((header, _)) => data.extend_from_slice(header),
+                None => data.extend_from_slice(&SampleInfo::EMPTY_HEADER),
+            }
+        }
+        data.push(positions.len() as u8);
+        data.push(127);
+        data.extend_from_slice(&order);
+        // Pro Tracker changes the tag when there are more than 64 patterns
+        if num_patterns > 64 {
+            data.extend_from_slice(b"M!K!");
+        } else {
+            data.extend_from_slice(b"M.K.");
+        }
+        for pattern_idx in 0..num_patterns {
+            match self.patterns.get(pattern_idx) {
+                Some(pattern) => data.extend_from_slice(pattern),
+                None => data.extend_from_slice(&[0; Pattern::PATTERN_LEN_4CH]),
+            }
+        }
+        for (_, pcm) in self.samples.iter() {
+            data.extend_from_slice(pcm);
+        }
+        data
+    }
+}
+
+/// A note in the song, from [`ProTrackerModule::note_events()`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NoteEvent {
+    /// Which channel plays the note
+    pub channel: u8,
+    /// When the note starts, in ticks from the start of the song
+    pub start_tick: u32,
+    /// How many ticks the note lasts for
+    pub duration_ticks: u32,
+    /// The period of the note, which sets its pitch
+    pub period: u16,
+}
+
+/// Works through a song, producing notes.
+///
+/// Generated by [`ProTrackerModule::note_events()`].
+pub struct NoteEventIter<'a> {
+    parent: &'a ProTrackerModule<'a>,
+    /// Index into the order list
+    position: usize,
+    /// The next line to play
+    row: u8,
+    /// When the next line starts
+    tick: u32,
+    ticks_per_line: u32,
+    /// The start tick and period of any note playing on each channel
+    playing: [Option<(u32, u16)>; MAX_CHANNELS],
+    /// Notes which have finished but not yet been handed out
+    ended: [Option<NoteEvent>; MAX_CHANNELS],
+}
+
+impl<'a> NoteEventIter<'a> {
+    /// Get the next line of the song, if any.
+    fn next_line(&mut self) -> Option<Line> {
+        let pattern_no = *self.parent.song_positions().get(self.position)?;
+        let line = self.parent.pattern(pattern_no)?.line(self.row)?;
+        self.row += 1;
+        if self.row == 64 {
+            self.row = 0;
+            self.position += 1;
+        }
+        Some(line)
+    }
+
+    /// Stop the note on a channel, if one is playing.
+    fn end_note(&mut self, channel: usize) {
+        if let Some((start_tick, period)) = self.playing[channel].take() {
+            self.ended[channel] = Some(NoteEvent {
+                channel: channel as u8,
+                start_tick,
+                duration_ticks: self.tick - start_tick,
+                period,
+            });
+        }
+    }
+}
+
+impl<'a> Iterator for NoteEventIter<'a> {
+    type Item = NoteEvent;
+
+    fn next(&mut self) -> Option<NoteEvent> {
+        loop {
+            if let Some(event) = self.ended.iter_mut().find_map(|e| e.take()) {
+                return Some(event);
+            }
+            let Some(line) = self.next_line() else {
+                // The song is over, so stop everything
+                for channel in 0..self.playing.len() {
+                    self.end_note(channel);
+                }
+                return self.ended.iter_mut().find_map(|e| e.take());
+            };
+            for note in line.channels().iter() {
+                match note.effect() {
+                    Some(Effect::SetSpeed(value)) if (1..=31).contains(&value) => {
+                        self.ticks_per_line = u32::from(value);
+                    }
+                    _ => {}
+                }
+            }
+            for (channel, note) in line.channels().iter().enumerate() {
+                let silenced = note.effect() == Some(Effect::SetVolume(0));
+                if note.period() != 0 || silenced {
+                    self.end_note(channel);
+                }
+                if note.period() != 0 && !silenced {
+                    self.playing[channel] = Some((self.tick, note.period()));
+                }
+            }
+            self.tick += self.ticks_per_line;
+        }
+    }
+}
+
 /// Represents a pattern
 ///
-/// A pattern is 1024 bytes, comprised of 64 notes, with 4 channels per note and 4 bytes per channel.
+/// A pattern is comprised of 64 lines, with a note for each channel on every
+/// line, and 4 bytes per note. A four channel pattern is therefore 1024
+/// bytes.
 pub struct Pattern<'a> {
     pattern_no: u8,
     parent: &'a ProTrackerModule<'a>,
 }
 
 impl<'a> Pattern<'a> {
-    const PATTERN_INFO_OFFSET: usize = 1084;
-    const PATTERN_LEN: usize = 1024;
+    /// The size of a four channel pattern
+    const PATTERN_LEN_4CH: usize = 1024;
 
     fn metadata_bytes(&self) -> &[u8] {
-        let start = Self::PATTERN_INFO_OFFSET + (usize::from(self.pattern_no) * Self::PATTERN_LEN);
-        let end = start + Self::PATTERN_LEN;
+        let len = self.parent.pattern_len();
+        let start = self.parent.pattern_offset() + (usize::from(self.pattern_no) * len);
+        let end = start + len;
         &self.parent.data[start..end]
     }
 
+    /// Which pattern this is
+    pub fn pattern_no(&self) -> u8 {
+        self.pattern_no
+    }
+
     /// Grab one specific line from a pattern
-    pub fn line(&self, index: u8) -> Option<Line<4>> {
+    pub fn line(&self, index: u8) -> Option<Line> {
         let mut iter = LineIter {
             note: index,
             parent: self,
@@ -157,13 +1487,57 @@ impl<'a> Pattern<'a> {
         iter.next()
     }
 
+    /// Which samples this pattern plays, as a bitmask with bit `n` set for
+    /// sample `n`.
+    fn used_samples(&self) -> u32 {
+        let mut used = 0;
+        for line in self.lines() {
+            for note in line.channels().iter() {
+                // There are only 31 samples, but the field can hold more
+                used |= 1u32.checked_shl(u32::from(note.sample_no())).unwrap_or(0);
+            }
+        }
+        // Sample zero means "no sample"
+        used & !1
+    }
+
+    /// Does this pattern have nothing in it at all?
+    pub fn is_empty(&self) -> bool {
+        // An empty note is all zeros, so we don't need to decode anything
+        self.metadata_bytes().iter().all(|b| *b == 0)
+    }
+
     /// Iterate through all the lines in a pattern
-    pub fn lines(&self) -> LineIter {
+    pub fn lines(&self) -> LineIter<'_> {
         LineIter {
             note: 0,
             parent: self,
         }
     }
+
+    /// Copy every note in the pattern out into a grid, indexed by line and
+    /// then by channel.
+    ///
+    /// The grid has room for `N` channels. Any channels beyond that are left
+    /// out, and if the pattern has fewer channels, the rest are left empty.
+    pub fn to_grid<const N: usize>(&self) -> [[Note; N]; 64] {
+        let mut grid = [[Note { data: [0; 4] }; N]; 64];
+        for (row, line) in grid.iter_mut().zip(self.lines()) {
+            for (grid_note, note) in row.iter_mut().zip(line.channels()) {
+                *grid_note = *note;
+            }
+        }
+        grid
+    }
+
+    /// Decode every line of a four channel pattern at once, without
+    /// allocating.
+    ///
+    /// The array takes 1 KiB. This is [`Pattern::to_grid`] with four
+    /// channels, so see that for what happens with other channel counts.
+    pub fn to_array(&self) -> [[Note; 4]; 64] {
+        self.to_grid()
+    }
 }
 
 /// Lets you iterate through the notes in a pattern
@@ -173,61 +1547,61 @@ pub struct LineIter<'a> {
 }
 
 impl<'a> Iterator for LineIter<'a> {
-    type Item = Line<4>;
+    type Item = Line;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.note >= 64 {
             return None;
         }
         let data = self.parent.metadata_bytes();
-        let offset = usize::from(self.note) * 16;
+        let num_channels = self.parent.parent.channel_count();
+        let offset = usize::from(self.note) * num_channels * 4;
         self.note += 1;
-        Some(Line {
-            channel: [
-                Note {
-                    data: [
-                        data[offset],
-                        data[offset + 1],
-                        data[offset + 2],
-                        data[offset + 3],
-                    ],
-                },
-                Note {
-                    data: [
-                        data[offset + 4],
-                        data[offset + 5],
-                        data[offset + 6],
-                        data[offset + 7],
-                    ],
-                },
-                Note {
-                    data: [
-                        data[offset + 8],
-                        data[offset + 9],
-                        data[offset + 10],
-                        data[offset + 11],
-                    ],
-                },
-                Note {
-                    data: [
-                        data[offset + 12],
-                        data[offset + 13],
-                        data[offset + 14],
-                        data[offset + 15],
-                    ],
-                },
-            ],
-        })
+        let mut line = Line {
+            channel: [Note { data: [0; 4] }; MAX_CHANNELS],
+            num_channels: num_channels as u8,
+        };
+        for (note, bytes) in line
+            .channel
+            .iter_mut()
+            .zip(data[offset..offset + (num_channels * 4)].chunks_exact(4))
+        {
+            note.data = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        }
+        Some(line)
     }
 }
 
-/// A set of notes, one per channel, for a line in a pattern.
-pub struct Line<const NUM_CHANNELS: usize> {
-    /// An array of channels
-    pub channel: [Note; NUM_CHANNELS],
+/// A set of notes, one per channel, for a line in a pattern.
+#[derive(Copy, Clone)]
+pub struct Line {
+    channel: [Note; MAX_CHANNELS],
+    num_channels: u8,
+}
+
+impl Line {
+    /// The notes on this line, one for each channel
+    pub fn channels(&self) -> &[Note] {
+        &self.channel[0..usize::from(self.num_channels)]
+    }
+
+    /// The note on one channel of this line, or `None` if the module does
+    /// not have that many channels.
+    ///
+    /// This replaces the public `channel` array that `Line` had when every
+    /// module was assumed to have four channels.
+    pub fn channel(&self, channel_no: usize) -> Option<&Note> {
+        self.channels().get(channel_no)
+    }
 }
 
 /// Conversion from period to musical note
+///
+/// Covers the three octaves Pro Tracker can play, with no finetune, from
+/// the lowest note to the highest. Notes are named as Pro Tracker shows
+/// them: three characters, being the letter, then `-` for a natural or `♯`
+/// for a sharp, then the octave, except that the sharp sign goes after the
+/// octave (so `C-2` then `C2♯`). Note that `♯` is not ASCII.
 pub static PERIOD_NOTE_MAP: &[(u16, &str)] = &[
     (856, "C-1"),
     (808, "C1♯"),
@@ -267,23 +1641,119 @@ pub static PERIOD_NOTE_MAP: &[(u16, &str)] = &[
     (113, "B-3"),
 ];
 
+/// Find the period for a musical note, like `C-2`.
+///
+/// The name must match an entry in [`PERIOD_NOTE_MAP`] exactly, so sharps
+/// must be written with `♯`. Returns `None` for anything else.
+pub fn period_for_note(name: &str) -> Option<u16> {
+    PERIOD_NOTE_MAP
+        .iter()
+        .find(|(_, note)| *note == name)
+        .map(|(period, _)| *period)
+}
+
+/// Find the period for a note, given its place in [`PERIOD_NOTE_MAP`].
+///
+/// Zero is C-1, up to 35 for B-3, as returned by [`Note::note_index`].
+/// Returns `None` for anything higher.
+pub fn period_from_index(index: u8) -> Option<u16> {
+    PERIOD_NOTE_MAP
+        .get(usize::from(index))
+        .map(|(period, _)| *period)
+}
+
 /// Move a period up by a number of half-steps
 ///
 /// Used for Arpeggios
 pub fn shift_period(period: u16, half_steps: u8) -> Option<u16> {
-    if let Ok(idx) = PERIOD_NOTE_MAP.binary_search_by(|(n, _)| n.cmp(&period)) {
+    // The table runs from the longest period to the shortest
+    if let Ok(idx) = PERIOD_NOTE_MAP.binary_search_by(|(n, _)| period.cmp(n)) {
         PERIOD_NOTE_MAP.get(idx + half_steps as usize).map(|n| n.0)
     } else {
         None
     }
 }
 
+/// How much each finetune value (-8 to 7) scales a period by, in 32768ths.
+///
+/// Each step of finetune is an eighth of a half-step.
+const FINETUNE_SCALE: [u32; 16] = [
+    34716, 34467, 34219, 33973, 33728, 33486, 33245, 33005, 32768, 32532, 32298, 32066, 31835,
+    31606, 31379, 31153,
+];
+
+/// Move a period from a finetuned sample up by a number of half-steps
+///
+/// Unlike [`shift_period`], the period need not be in [`PERIOD_NOTE_MAP`].
+/// The finetune is taken off, the nearest note in the table is shifted, and
+/// then the finetune is put back. Returns `None` if the period is more than
+/// about a quarter-tone from any note, or the shifted note is off the end
+/// of the table.
+pub fn shift_period_finetuned(period: u16, half_steps: u8, finetune: i8) -> Option<u16> {
+    let scale = FINETUNE_SCALE[usize::from((finetune.clamp(-8, 7) + 8) as u8)];
+    let untuned = (u32::from(period) * 32768 + scale / 2) / scale;
+    let (idx, (nearest, _)) = PERIOD_NOTE_MAP
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, (n, _))| u32::from(*n).abs_diff(untuned))?;
+    let nearest = u32::from(*nearest);
+    // A half-step is about 6% of the period
+    if nearest.abs_diff(untuned) * 32 > nearest {
+        return None;
+    }
+    let (target, _) = PERIOD_NOTE_MAP.get(idx + usize::from(half_steps))?;
+    Some(((u32::from(*target) * scale + 16384) / 32768) as u16)
+}
+
+/// Apply a volume slide to a volume, saturating to the range `0..=63`.
+pub fn apply_volume_slide(volume: u8, delta: i8) -> u8 {
+    (i16::from(volume) + i16::from(delta)).clamp(0, 63) as u8
+}
+
+/// A square root, for `no_std` targets without a maths library.
+pub(crate) fn sqrt(value: f64) -> f64 {
+    if value <= 0.0 {
+        return 0.0;
+    }
+    // Newton's method, starting above the answer so each guess is smaller
+    // than the last until we run out of precision
+    let mut guess = value.max(1.0);
+    for _ in 0..64 {
+        let next = (guess + value / guess) / 2.0;
+        if next >= guess {
+            break;
+        }
+        guess = next;
+    }
+    guess
+}
+
 /// A note that can be played on a given channel.
+///
+/// Notes are equal if they are stored as the same four bytes. The `Debug`
+/// output is the note as a tracker shows it, like `C-2 05 C20`.
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct Note {
     data: [u8; 4],
 }
 
 impl Note {
+    /// Make a note, as it would be stored in a pattern.
+    ///
+    /// The sample number is from 0 (keep the current sample) to 31, and the
+    /// period is 12 bits. A period of zero means no new note.
+    pub fn new(sample_no: u8, period: u16, effect: Option<Effect>) -> Note {
+        let effect = effect.map(|e| e.to_u16()).unwrap_or(0);
+        Note {
+            data: [
+                (sample_no & 0xF0) | ((period >> 8) as u8 & 0x0F),
+                period as u8,
+                (sample_no << 4) | ((effect >> 8) as u8 & 0x0F),
+                effect as u8,
+            ],
+        }
+    }
+
     /// Get which sample should be played
     pub fn sample_no(&self) -> u8 {
         self.data[0] & 0xF0 | (self.data[2] & 0xF0) >> 4
@@ -296,17 +1766,45 @@ impl Note {
         u16::from(self.data[0] & 0x0F) << 8 | u16::from(self.data[1])
     }
 
+    /// The rate, in Hz, at which sample data is played for this note, or
+    /// `None` if there is no note.
+    ///
+    /// This uses [`NTSC_CLOCK`], which is the convention trackers use when
+    /// quoting sample rates, so a C-2 (period 428) gives 8363 Hz. Divide
+    /// [`PAL_CLOCK`] by [`Note::period`] yourself if you want the rate a PAL
+    /// Amiga would actually play at.
+    pub fn frequency(&self) -> Option<u32> {
+        match self.period() {
+            0 => None,
+            period => Some(NTSC_CLOCK / u32::from(period)),
+        }
+    }
+
     /// The musical note, if any, this note matches
     pub fn musical_note(&self) -> Option<&'static str> {
+        self.note_index()
+            .map(|idx| PERIOD_NOTE_MAP[usize::from(idx)].1)
+    }
+
+    /// Where this note's period is in [`PERIOD_NOTE_MAP`], if it matches one
+    /// exactly.
+    ///
+    /// Zero is C-1, and each step up is a half-step higher, up to 35 for B-3.
+    pub fn note_index(&self) -> Option<u8> {
         let period = self.period();
-        let position = PERIOD_NOTE_MAP
+        PERIOD_NOTE_MAP
             .binary_search_by(|probe| period.cmp(&probe.0))
-            .ok();
-        if let Some(position) = position {
-            PERIOD_NOTE_MAP.get(position).map(|info| info.1)
-        } else {
-            None
-        }
+            .ok()
+            .map(|idx| idx as u8)
+    }
+
+    /// Does this note have an effect?
+    ///
+    /// The same as `self.effect().is_some()`, but without decoding the
+    /// effect, which is worth having when scanning every note in a module.
+    #[inline]
+    pub fn has_effect(&self) -> bool {
+        self.effect_u16() != 0
     }
 
     /// Get the effect command
@@ -317,19 +1815,108 @@ impl Note {
     /// Get the effect command
     ///
     /// In the format 0x0NMM where N is the command and MM is the argument
+    #[inline]
     pub fn effect_u16(&self) -> u16 {
         u16::from(self.data[2] & 0x0F) << 8 | u16::from(self.data[3])
     }
 
+    /// Get the effect command number, from 0 to 15
+    ///
+    /// This is the first hex digit of the effect, as a tracker shows it.
+    #[inline]
+    pub fn effect_command(&self) -> u8 {
+        self.data[2] & 0x0F
+    }
+
+    /// Get the raw effect argument
+    ///
+    /// This is the last two hex digits of the effect, as a tracker shows it.
+    #[inline]
+    pub fn effect_arg(&self) -> u8 {
+        self.data[3]
+    }
+
+    /// Get the effect argument as a signed slide amount.
+    ///
+    /// Uses the volume slide convention: a non-zero high nibble slides up by
+    /// that amount, otherwise the low nibble slides down. This is only
+    /// meaningful for slide-type effects - for anything else use
+    /// [`Note::effect`] instead.
+    pub fn effect_arg_signed(&self) -> i8 {
+        Effect::slide_amount(self.data[3])
+    }
+
+    /// Decode everything in this note at once.
+    pub fn decode(&self) -> DecodedNote {
+        DecodedNote {
+            sample_no: self.sample_no(),
+            period: self.period(),
+            note: self.musical_note(),
+            effect: self.effect(),
+        }
+    }
+
     /// Does this note do nothing?
     pub fn is_empty(&self) -> bool {
         self.effect_u16() == 0 && self.period() == 0 && self.sample_no() == 0
     }
+
+    /// Does this note give a new pitch, and so start a note playing?
+    ///
+    /// Unlike [`Note::is_empty`], any effect is ignored.
+    #[inline]
+    pub fn has_pitch(&self) -> bool {
+        self.period() != 0
+    }
+
+    /// Does this note pick a sample, and so reset the volume?
+    #[inline]
+    pub fn triggers_sample(&self) -> bool {
+        self.sample_no() != 0
+    }
+
+    /// Do these notes play the same sample at the same period?
+    ///
+    /// Effects are ignored, so this is handy for spotting repeated phrases.
+    pub fn same_pitch(&self, other: &Note) -> bool {
+        self.sample_no() == other.sample_no() && self.period() == other.period()
+    }
+}
+
+impl core::fmt::Debug for Note {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // A period which is not on the scale is shown as `???`
+        let note = match self.period() {
+            0 => "---",
+            _ => self.musical_note().unwrap_or("???"),
+        };
+        write!(
+            f,
+            "{} {:02} {:X}{:02X}",
+            note,
+            self.sample_no(),
+            self.effect_command(),
+            self.effect_arg()
+        )
+    }
+}
+
+/// The contents of a [`Note`], fully decoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DecodedNote {
+    /// Which sample should be played (zero if none)
+    pub sample_no: u8,
+    /// The sample period (i.e. pitch)
+    pub period: u16,
+    /// The musical note, if the period matches one
+    pub note: Option<&'static str>,
+    /// The effect command, if any
+    pub effect: Option<Effect>,
 }
 
 /// Represents an effect
 #[repr(u8)]
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Effect {
     /// Arpeggio
     Arpeggio(u8) = 0,
@@ -346,23 +1933,41 @@ pub enum Effect {
     /// Vibrato and volume slide
     VibratoSlide(u8) = 6,
     /// Tremolo
-    Tremelo(u8) = 7,
+    ///
+    /// The argument packs a speed and a depth, as with
+    /// [`Effect::Vibrato`]. See [`Effect::speed_and_depth`].
+    Tremolo(u8) = 7,
+    /// Set panning, from 0x00 (far left) to 0xFF (far right)
+    ///
+    /// Pro Tracker itself ignores this, but many other trackers support it.
+    SetPanning(u8) = 8,
     /// Set sample offset
     SampleOffset(u8) = 9,
     /// Volume slide
-    VolumeSlide(i8) = 10,
+    ///
+    /// Both amounts are stored, from 0 to 15. If both are set, Pro Tracker
+    /// only slides up.
+    VolumeSlide {
+        /// How much to slide up by on each tick (the high nibble)
+        up: u8,
+        /// How much to slide down by on each tick (the low nibble)
+        down: u8,
+    } = 10,
     /// Position jump
     PositionJump(u8) = 11,
     /// Set volume
     SetVolume(u8) = 12,
     /// Pattern break
     PatternBreak(u8) = 13,
+    /// One of the extended (0xExy) effects
+    Extended(ExtendedEffect) = 14,
     /// Set speed
     SetSpeed(u8) = 15,
 }
 
 impl Effect {
     /// Try and parse a 16-bit effect value
+    #[inline]
     pub const fn try_from(value: u16) -> Option<Effect> {
         if value == 0 {
             return None;
@@ -376,32 +1981,239 @@ impl Effect {
             4 => Some(Effect::Vibrato(arg)),
             5 => Some(Effect::SlideNoteVolume(arg)),
             6 => Some(Effect::VibratoSlide(arg)),
-            7 => Some(Effect::Tremelo(arg)),
+            7 => Some(Effect::Tremolo(arg)),
+            8 => Some(Effect::SetPanning(arg)),
             9 => Some(Effect::SampleOffset(arg)),
-            10 => Some(if arg >= 0x10 {
-                Effect::VolumeSlide((arg >> 4) as i8)
-            } else {
-                Effect::VolumeSlide(-(arg as i8))
+            10 => Some(Effect::VolumeSlide {
+                up: arg >> 4,
+                down: arg & 0x0F,
             }),
             11 => Some(Effect::PositionJump(arg)),
             12 => Some(Effect::SetVolume(arg)),
             13 => Some(Effect::PatternBreak(arg)),
+            14 => Some(Effect::Extended(ExtendedEffect::from_arg(arg))),
             15 => Some(Effect::SetSpeed(arg)),
             _ => None,
         }
     }
+
+    /// Convert a slide argument into a signed amount.
+    ///
+    /// The high nibble slides up, and takes priority over the low nibble,
+    /// which slides down.
+    const fn slide_amount(arg: u8) -> i8 {
+        if arg >= 0x10 {
+            (arg >> 4) as i8
+        } else {
+            -(arg as i8)
+        }
+    }
+
+    /// Split the argument of a Vibrato or Tremolo effect into its speed
+    /// (the high nibble) and depth (the low nibble).
+    ///
+    /// Returns `None` for any other effect.
+    pub const fn speed_and_depth(&self) -> Option<(u8, u8)> {
+        match *self {
+            Effect::Vibrato(arg) | Effect::Tremolo(arg) => Some((arg >> 4, arg & 0x0F)),
+            _ => None,
+        }
+    }
+
+    /// Encode this effect as the 16-bit value stored in a pattern.
+    ///
+    /// The inverse of [`Effect::try_from`]. Volume slides of more than 15
+    /// in either direction cannot be stored, and are truncated.
+    pub const fn to_u16(&self) -> u16 {
+        let (command, arg) = match *self {
+            Effect::Arpeggio(arg) => (0, arg),
+            Effect::SlideUp(arg) => (1, arg),
+            Effect::SlideDown(arg) => (2, arg),
+            Effect::SlideToNote(arg) => (3, arg),
+            Effect::Vibrato(arg) => (4, arg),
+            Effect::SlideNoteVolume(arg) => (5, arg),
+            Effect::VibratoSlide(arg) => (6, arg),
+            Effect::Tremolo(arg) => (7, arg),
+            Effect::SetPanning(arg) => (8, arg),
+            Effect::SampleOffset(arg) => (9, arg),
+            Effect::VolumeSlide { up, down } => (10, (up & 0x0F) << 4 | (down & 0x0F)),
+            Effect::PositionJump(arg) => (11, arg),
+            Effect::SetVolume(arg) => (12, arg),
+            Effect::PatternBreak(arg) => (13, arg),
+            Effect::Extended(extended) => (14, extended.to_arg()),
+            Effect::SetSpeed(arg) => (15, arg),
+        };
+        (command << 8) | arg as u16
+    }
+
+    /// Format this effect as a tracker would show it, like `C40`.
+    ///
+    /// The command is one hex digit, and the argument two. The result is
+    /// always ASCII.
+    pub fn to_tracker_string(&self) -> [u8; 3] {
+        const HEX: &[u8; 16] = b"0123456789ABCDEF";
+        let value = self.to_u16();
+        [
+            HEX[usize::from((value >> 8) & 0x0F)],
+            HEX[usize::from((value >> 4) & 0x0F)],
+            HEX[usize::from(value & 0x0F)],
+        ]
+    }
+
+    /// When during a line does this effect do its work?
+    pub const fn timing(&self) -> EffectTiming {
+        match self {
+            Effect::Arpeggio(_)
+            | Effect::SlideUp(_)
+            | Effect::SlideDown(_)
+            | Effect::SlideToNote(_)
+            | Effect::Vibrato(_)
+            | Effect::SlideNoteVolume(_)
+            | Effect::VibratoSlide(_)
+            | Effect::Tremolo(_)
+            | Effect::VolumeSlide { .. } => EffectTiming::PerTick,
+            Effect::SetPanning(_)
+            | Effect::SampleOffset(_)
+            | Effect::PositionJump(_)
+            | Effect::SetVolume(_)
+            | Effect::PatternBreak(_)
+            | Effect::SetSpeed(_) => EffectTiming::OnLine,
+            Effect::Extended(extended) => extended.timing(),
+        }
+    }
 }
 
-/// Represents a sample
-pub struct Sample<'a> {
+/// An extended effect, from command 0xE.
+///
+/// The high nibble of the effect argument picks the effect, and the low
+/// nibble is passed to it as a parameter.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExtendedEffect {
+    /// Turn the Amiga's audio filter on (0) or off (1)
+    SetFilter(u8),
+    /// Slide the period up, once, on the first tick
+    FineSlideUp(u8),
+    /// Slide the period down, once, on the first tick
+    FineSlideDown(u8),
+    /// Make Slide to Note jump in whole semitones (1), or not (0)
+    GlissandoControl(u8),
+    /// Pick the waveform used by Vibrato
+    SetVibratoWaveform(u8),
+    /// Override the finetune of the sample
+    SetFinetune(u8),
+    /// Mark the start of a loop (0), or loop back this many times
+    PatternLoop(u8),
+    /// Pick the waveform used by Tremolo
+    SetTremoloWaveform(u8),
+    /// Karplus-Strong string synthesis, in some versions of Pro Tracker.
+    /// Usually unused.
+    KarplusStrong(u8),
+    /// Restart the sample every this many ticks
+    RetriggerNote(u8),
+    /// Raise the volume, once, on the first tick
+    FineVolumeSlideUp(u8),
+    /// Lower the volume, once, on the first tick
+    FineVolumeSlideDown(u8),
+    /// Silence the note after this many ticks
+    NoteCut(u8),
+    /// Hold the note back for this many ticks
+    NoteDelay(u8),
+    /// Repeat this line this many times over, without retriggering notes
+    PatternDelay(u8),
+    /// Invert the sample loop, at the given speed
+    InvertLoop(u8),
+}
+
+impl ExtendedEffect {
+    /// Decode the argument to a 0xE effect
+    pub const fn from_arg(arg: u8) -> ExtendedEffect {
+        let param = arg & 0x0F;
+        match arg >> 4 {
+            0x0 => ExtendedEffect::SetFilter(param),
+            0x1 => ExtendedEffect::FineSlideUp(param),
+            0x2 => ExtendedEffect::FineSlideDown(param),
+            0x3 => ExtendedEffect::GlissandoControl(param),
+            0x4 => ExtendedEffect::SetVibratoWaveform(param),
+            0x5 => ExtendedEffect::SetFinetune(param),
+            0x6 => ExtendedEffect::PatternLoop(param),
+            0x7 => ExtendedEffect::SetTremoloWaveform(param),
+            0x8 => ExtendedEffect::KarplusStrong(param),
+            0x9 => ExtendedEffect::RetriggerNote(param),
+            0xA => ExtendedEffect::FineVolumeSlideUp(param),
+            0xB => ExtendedEffect::FineVolumeSlideDown(param),
+            0xC => ExtendedEffect::NoteCut(param),
+            0xD => ExtendedEffect::NoteDelay(param),
+            0xE => ExtendedEffect::PatternDelay(param),
+            _ => ExtendedEffect::InvertLoop(param),
+        }
+    }
+
+    /// Encode this as the argument to a 0xE effect.
+    ///
+    /// The inverse of [`ExtendedEffect::from_arg`]. Parameters above 15
+    /// cannot be stored, and are truncated.
+    pub const fn to_arg(&self) -> u8 {
+        let (sub, param) = match *self {
+            ExtendedEffect::SetFilter(param) => (0x0, param),
+            ExtendedEffect::FineSlideUp(param) => (0x1, param),
+            ExtendedEffect::FineSlideDown(param) => (0x2, param),
+            ExtendedEffect::GlissandoControl(param) => (0x3, param),
+            ExtendedEffect::SetVibratoWaveform(param) => (0x4, param),
+            ExtendedEffect::SetFinetune(param) => (0x5, param),
+            ExtendedEffect::PatternLoop(param) => (0x6, param),
+            ExtendedEffect::SetTremoloWaveform(param) => (0x7, param),
+            ExtendedEffect::KarplusStrong(param) => (0x8, param),
+            ExtendedEffect::RetriggerNote(param) => (0x9, param),
+            ExtendedEffect::FineVolumeSlideUp(param) => (0xA, param),
+            ExtendedEffect::FineVolumeSlideDown(param) => (0xB, param),
+            ExtendedEffect::NoteCut(param) => (0xC, param),
+            ExtendedEffect::NoteDelay(param) => (0xD, param),
+            ExtendedEffect::PatternDelay(param) => (0xE, param),
+            ExtendedEffect::InvertLoop(param) => (0xF, param),
+        };
+        (sub << 4) | (param & 0x0F)
+    }
+
+    /// When during a line does this effect do its work?
+    pub const fn timing(&self) -> EffectTiming {
+        match self {
+            ExtendedEffect::RetriggerNote(_)
+            | ExtendedEffect::NoteCut(_)
+            | ExtendedEffect::NoteDelay(_)
+            | ExtendedEffect::InvertLoop(_) => EffectTiming::PerTick,
+            _ => EffectTiming::OnLine,
+        }
+    }
+}
+
+/// When an [`Effect`] is applied.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EffectTiming {
+    /// Once, on the first tick of the line, when the note is triggered
+    OnLine,
+    /// On every tick of the line except the first
+    PerTick,
+}
+
+/// The metadata for a sample, without its PCM data.
+///
+/// Generated by [`ProTrackerModule::sample_info()`]. Use
+/// [`ProTrackerModule::sample()`] if you need the PCM data.
+///
+/// ```compile_fail
+/// # let data = [0u8; 2108];
+/// let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+/// let info = pt.sample_info(1).unwrap();
+/// // Metadata only - there is no PCM data to be had
+/// info.raw_sample_bytes();
+/// ```
+pub struct SampleInfo<'a> {
     /// A one-based indexed into the sample table
     sample_no: u8,
     /// A volume, from 0 to 63
     volume: u8,
     /// Finetune
     finetune: u8,
-    /// Where in the MOD file the sample starts
-    file_offset: usize,
     /// The repeat length
     repeat_length: u16,
     /// The repeat point
@@ -412,14 +2224,33 @@ pub struct Sample<'a> {
     parent: &'a ProTrackerModule<'a>,
 }
 
-impl<'a> core::fmt::Debug for Sample<'a> {
+/// Represents a sample, including its PCM data.
+///
+/// All of the [`SampleInfo`] methods are available too.
+#[derive(Debug)]
+pub struct Sample<'a> {
+    /// The metadata for the sample
+    info: SampleInfo<'a>,
+    /// Where in the MOD file the sample starts
+    file_offset: usize,
+}
+
+impl<'a> core::ops::Deref for Sample<'a> {
+    type Target = SampleInfo<'a>;
+
+    fn deref(&self) -> &SampleInfo<'a> {
+        &self.info
+    }
+}
+
+impl<'a> core::fmt::Debug for SampleInfo<'a> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.debug_struct("Sample")
+        f.debug_struct("SampleInfo")
             .field("sample_no", &self.sample_no)
-            .field("file_offset", &self.file_offset)
             .field("name", &core::str::from_utf8(self.name()).unwrap_or("?"))
             .field("sample_length_bytes", &self.sample_length_bytes())
             .field("finetune", &self.finetune())
+            .field("finetune_raw", &self.finetune)
             .field("volume", &self.volume())
             .field("repeat_point", &self.repeat_point_bytes())
             .field("repeat_length", &self.repeat_length_bytes())
@@ -427,18 +2258,28 @@ impl<'a> core::fmt::Debug for Sample<'a> {
     }
 }
 
-impl<'a> Sample<'a> {
+impl<'a> SampleInfo<'a> {
     const SAMPLE_INFO_OFFSET: usize = 20;
     const SAMPLE_INFO_LEN: usize = 30;
+    /// The period of C-2 for each finetune value, 0 to 7 and then -8 to -1.
+    const C2_PERIODS: [u16; 16] = [
+        428, 425, 422, 419, 416, 413, 410, 407, 453, 450, 447, 444, 441, 437, 434, 431,
+    ];
     const SAMPLE_MAX_NAME_LEN: usize = 22;
-
-    /// Create a new sample
+    /// An empty sample, with the customary loop length of one word
+    #[cfg(feature = "alloc")]
+    const EMPTY_HEADER: [u8; 30] = {
+        let mut header = [0u8; 30];
+        header[29] = 1;
+        header
+    };
+
+    /// Read the metadata for a sample
     ///
     /// The sample_no must be `1..=31`.
-    fn new(sample_no: u8, file_offset: usize, parent: &'a ProTrackerModule<'a>) -> Sample<'a> {
-        let mut s = Sample {
+    fn new(sample_no: u8, parent: &'a ProTrackerModule<'a>) -> SampleInfo<'a> {
+        let mut s = SampleInfo {
             sample_no,
-            file_offset,
             parent,
             volume: 0,
             finetune: 0,
@@ -488,12 +2329,44 @@ impl<'a> Sample<'a> {
 
     /// Length of the sample in bytes
     pub fn sample_length_bytes(&self) -> usize {
-        usize::from(self.sample_length * 2)
+        usize::from(self.sample_length) * 2
+    }
+
+    /// The finetune value for the sample, as a signed number of eighths of
+    /// a semitone, from -8 to 7
+    pub fn finetune(&self) -> i8 {
+        // Finetune is a four bit signed value
+        ((self.finetune & 0x0F) as i8) << 4 >> 4
+    }
+
+    /// The rate at which this sample plays when given a C-2 (period 428),
+    /// taking the finetune into account.
+    ///
+    /// Other trackers may call this note C-3, or C-5. Samplers which want a
+    /// root key and sample rate should use this rate, with that note as the
+    /// root key. A sample with no finetune gives the classic 8363 Hz.
+    pub fn natural_sample_rate(&self) -> u32 {
+        let period = Self::C2_PERIODS[usize::from(self.finetune & 0x0F)];
+        NTSC_CLOCK / u32::from(period)
     }
 
-    /// The finetune value for the sample
-    pub fn finetune(&self) -> u8 {
-        self.finetune
+    /// The note to use as the root key when importing this sample into a
+    /// sampler at 8363 Hz.
+    ///
+    /// By convention a MOD sample is recorded so that it sounds as C-2 (what
+    /// some other trackers call C-3) when played at 8363 Hz. Finetune makes
+    /// the tracker play the sample slightly sharp or flat. Where that comes
+    /// to half a semitone or more (a finetune of 4 or more either way), the
+    /// root key moves a half-step the other way, so the note is still
+    /// played in tune. Alternatively, use C-2 with
+    /// [`SampleInfo::natural_sample_rate`].
+    pub fn root_note(&self) -> &'static str {
+        let index = match self.finetune() {
+            4.. => 11,
+            ..=-4 => 13,
+            _ => 12,
+        };
+        PERIOD_NOTE_MAP[index].1
     }
 
     /// The default volume of the sample
@@ -502,8 +2375,11 @@ impl<'a> Sample<'a> {
     }
 
     /// Does this sample repeat?
+    ///
+    /// A repeat length of 1 means no repeat. Some files use 0 instead, so
+    /// we treat that the same way.
     pub fn loops(&self) -> bool {
-        self.repeat_length != 1
+        self.repeat_length > 1
     }
 
     /// Where the sample should loop back to when repeating, in 16-bit units.
@@ -513,7 +2389,7 @@ impl<'a> Sample<'a> {
 
     /// Where the sample should loop back to when repeating, as a byte offset.
     pub fn repeat_point_bytes(&self) -> usize {
-        usize::from(self.repeat_point * 2)
+        usize::from(self.repeat_point) * 2
     }
 
     /// The length of the repeating portion, in 16-bit units
@@ -523,22 +2399,216 @@ impl<'a> Sample<'a> {
 
     /// The length of the repeating portion, in bytes
     pub fn repeat_length_bytes(&self) -> usize {
-        usize::from(self.repeat_length * 2)
+        usize::from(self.repeat_length) * 2
+    }
+
+    /// How many output frames one pass through the loop takes, when played
+    /// at the given period and output sample rate.
+    ///
+    /// Returns 0 if the sample does not loop.
+    pub fn loop_length_frames(&self, note_period: u16, output_rate: u32) -> usize {
+        if !self.loops() {
+            return 0;
+        }
+        let Some(step) =
+            Fractional::new_from_sample_rate(output_rate).apply_period_checked(note_period)
+        else {
+            return 0;
+        };
+        if step.inner == 0 {
+            return 0;
+        }
+        let loop_length = Fractional::new(self.repeat_length_bytes() as u32);
+        (loop_length.inner / step.inner) as usize
+    }
+}
+
+impl<'a> Sample<'a> {
+    /// Create a new sample
+    ///
+    /// The sample_no must be `1..=31`.
+    fn new(sample_no: u8, file_offset: usize, parent: &'a ProTrackerModule<'a>) -> Sample<'a> {
+        Sample {
+            info: SampleInfo::new(sample_no, parent),
+            file_offset,
+        }
+    }
+
+    /// The metadata for this sample
+    pub fn info(&self) -> &SampleInfo<'a> {
+        &self.info
+    }
+
+    /// Might this sample be longer than its header says?
+    ///
+    /// The length is stored as a 16-bit count of words, so it cannot go past
+    /// 128 KiB. Some trackers write longer samples anyway, and the length
+    /// wraps around. We spot this by looking for at least 128 KiB of data
+    /// left over after the last sample. We cannot tell which sample wrapped,
+    /// so every sample with data is flagged.
+    pub fn length_looks_truncated(&self) -> bool {
+        const WRAP_BYTES: usize = 0x10000 * 2;
+        self.sample_length != 0 && self.parent.trailing_data().len() >= WRAP_BYTES
     }
 
     /// The sample as 8-bit data
     pub fn raw_sample_bytes(&self) -> &[u8] {
+        &self.parent.data[self.pcm_range()]
+    }
+
+    /// Where in the file the sample data lives.
+    fn pcm_range(&self) -> core::ops::Range<usize> {
         // short-cut if sample is empty
         if self.sample_length == 0 || self.volume == 0 {
-            return &[];
+            return 0..0;
         };
         // This is where in the file the sample lives.
         let range = self.file_offset..(self.file_offset + self.sample_length_bytes());
-        self.parent.data.get(range).unwrap_or_else(|| {
+        let len = self.parent.data.len();
+        if range.end <= len {
+            range
+        } else {
             // This sample goes off the end of the file. Give them as much as we
-            // can instead.
-            &self.parent.data[self.file_offset..]
-        })
+            // can instead - which may be nothing at all.
+            self.file_offset.min(len)..len
+        }
+    }
+
+    /// The sample data as stored in the file, whatever the volume, padded
+    /// with zeros if the file has been cut short.
+    #[cfg(feature = "alloc")]
+    fn stored_pcm(&self) -> alloc::vec::Vec<u8> {
+        let len = self.parent.data.len();
+        let start = self.file_offset.min(len);
+        let end = (self.file_offset + self.sample_length_bytes()).min(len);
+        let mut pcm = self.parent.data[start..end].to_vec();
+        pcm.resize(self.sample_length_bytes(), 0);
+        pcm
+    }
+
+    /// The audible part of the sample as 8-bit data.
+    ///
+    /// For a looping sample, playback never goes past the end of the loop, so
+    /// anything after it (which is often silence or garbage) is dropped. A
+    /// one-shot sample is returned in full.
+    pub fn trimmed_pcm(&self) -> &[u8] {
+        let data = self.raw_sample_bytes();
+        if self.loops() {
+            let loop_end = self.repeat_point_bytes() + self.repeat_length_bytes();
+            &data[0..loop_end.min(data.len())]
+        } else {
+            data
+        }
+    }
+
+    /// How much to scale this sample by to bring it to a standard loudness.
+    ///
+    /// The gain brings the RMS level of the sample to a quarter of full
+    /// scale, unless that would make the peaks clip, in which case it brings
+    /// the peaks to full scale. Silent or empty samples get a gain of 1.0.
+    pub fn suggested_gain(&self) -> f32 {
+        const TARGET_RMS: f64 = 32.0;
+        let data = self.raw_sample_bytes();
+        let peak = self.peak_amplitude();
+        if peak == 0 {
+            return 1.0;
+        }
+        let sum_squares: f64 = data
+            .iter()
+            .map(|b| f64::from(*b as i8) * f64::from(*b as i8))
+            .sum();
+        let rms = sqrt(sum_squares / data.len() as f64);
+        let gain = TARGET_RMS / rms;
+        let max_gain = 128.0 / f64::from(peak);
+        gain.min(max_gain) as f32
+    }
+
+    /// The largest distance of any sample value from zero, from 0 to 128.
+    ///
+    /// Handy for scaling a waveform drawing. Empty samples give 0.
+    pub fn peak_amplitude(&self) -> u8 {
+        self.raw_sample_bytes()
+            .iter()
+            .map(|b| (*b as i8).unsigned_abs())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The sample as signed 8-bit data, faded out to silence over the last
+    /// `fade_frames` frames.
+    ///
+    /// Useful for exporting a one-shot sample which would otherwise end with
+    /// a click. The fade is linear, and `fade_frames` is clamped to the
+    /// length of the sample.
+    #[cfg(feature = "alloc")]
+    pub fn pcm_with_fade(&self, fade_frames: usize) -> alloc::vec::Vec<i8> {
+        let data = self.raw_sample_bytes();
+        let fade_frames = fade_frames.min(data.len());
+        let fade_start = data.len() - fade_frames;
+        data.iter()
+            .enumerate()
+            .map(|(idx, byte)| {
+                let value = *byte as i8;
+                if idx < fade_start {
+                    value
+                } else {
+                    let remaining = (data.len() - 1 - idx) as i32;
+                    (i32::from(value) * remaining / fade_frames as i32) as i8
+                }
+            })
+            .collect()
+    }
+
+    /// The sample as a mono, 8-bit PCM WAV file, playing at the given rate.
+    ///
+    /// WAV files hold 8-bit data as unsigned values, so each byte is offset
+    /// by 128 on the way out.
+    #[cfg(feature = "alloc")]
+    pub fn to_wav(&self, sample_rate: u32) -> alloc::vec::Vec<u8> {
+        let pcm = self.raw_sample_bytes();
+        let mut wav = alloc::vec::Vec::with_capacity(44 + pcm.len() + 1);
+        wav.extend_from_slice(&wav_header(1, 8, sample_rate, pcm.len() as u32));
+        wav.extend(pcm.iter().map(|b| b ^ 0x80));
+        // Chunks must be an even number of bytes long
+        if pcm.len() & 1 != 0 {
+            wav.push(0);
+        }
+        wav
+    }
+
+    /// Create an iterator that hands out just the looping part of the
+    /// sample, over and over.
+    ///
+    /// The iterator is empty if the sample does not loop.
+    pub fn loop_iter(&self) -> impl Iterator<Item = u8> + '_ {
+        let data = if self.loops() {
+            let loop_start = self.repeat_point_bytes();
+            let loop_end = loop_start + self.repeat_length_bytes();
+            self.raw_sample_bytes()
+                .get(loop_start..loop_end)
+                .unwrap_or_default()
+        } else {
+            &[]
+        };
+        data.iter().cloned().cycle()
+    }
+
+    /// How big a jump there is in the waveform where the loop wraps around,
+    /// from the last byte of the loop back to the first.
+    ///
+    /// A large value will be heard as a click every time the loop repeats.
+    /// Returns `None` if the sample does not loop, or the loop lies outside
+    /// the sample data.
+    pub fn loop_seam_discontinuity(&self) -> Option<u8> {
+        if !self.loops() {
+            return None;
+        }
+        let loop_start = self.repeat_point_bytes();
+        let loop_end = loop_start + self.repeat_length_bytes();
+        let data = self.raw_sample_bytes().get(loop_start..loop_end)?;
+        let first = i16::from(*data.first()? as i8);
+        let last = i16::from(*data.last()? as i8);
+        Some((last - first).unsigned_abs() as u8)
     }
 
     /// Create an iterator that will hand out samples, handling looping/repeating as required.
@@ -550,6 +2620,78 @@ impl<'a> Sample<'a> {
             position: 0,
         }
     }
+
+    /// Like [`Sample::sample_bytes_iter`], but hands out the samples as the
+    /// signed values they really are.
+    pub fn sample_i8_iter(&'a self) -> impl Iterator<Item = i8> + 'a {
+        self.sample_bytes_iter().map(|b| b as i8)
+    }
+
+    /// Create an iterator that steps through the sample by `step` bytes at a
+    /// time, blending between neighbouring bytes when it lands part way
+    /// between them.
+    ///
+    /// Looping is handled as in [`Sample::sample_bytes_iter`], and at the
+    /// end of the loop we blend back towards the start of the loop.
+    pub fn sample_bytes_iter_interpolated(
+        &'a self,
+        step: Fractional,
+    ) -> InterpolatedSampleIter<'a> {
+        let (loop_start, loop_end) = if self.loops() {
+            let loop_start = self.repeat_point_bytes();
+            (loop_start, Some(loop_start + self.repeat_length_bytes()))
+        } else {
+            (0, None)
+        };
+        InterpolatedSampleIter {
+            data: self.raw_sample_bytes(),
+            loop_start,
+            loop_end,
+            position: Fractional::default(),
+            step,
+        }
+    }
+}
+
+/// Generates linearly interpolated PCM samples from a sample.
+///
+/// Generated by [`Sample::sample_bytes_iter_interpolated`]. Like
+/// [`SampleBytesIter`], this is infinite if the sample loops.
+pub struct InterpolatedSampleIter<'a> {
+    /// Our sample, as bytes
+    data: &'a [u8],
+    /// Where the loop starts, in bytes
+    loop_start: usize,
+    /// Where the loop ends, in bytes, if the sample loops
+    loop_end: Option<usize>,
+    /// Our current position, in bytes
+    position: Fractional,
+    /// How far to move on each time
+    step: Fractional,
+}
+
+impl<'a> Iterator for InterpolatedSampleIter<'a> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let index = self.position.as_index();
+        let this = *self.data.get(index)? as i8;
+        let next_index = match self.loop_end {
+            Some(loop_end) if index + 1 >= loop_end => self.loop_start,
+            _ => index + 1,
+        };
+        // Past the end of a one-shot sample, hold the last value
+        let next = self.data.get(next_index).map_or(this, |b| *b as i8);
+        let delta = i32::from(next) - i32::from(this);
+        let value = i32::from(this) + (delta * i32::from(self.position.fraction())) / 256;
+        self.position += self.step;
+        if let Some(loop_end) = self.loop_end {
+            self.position = self
+                .position
+                .wrap_loop(self.loop_start, loop_end - self.loop_start);
+        }
+        Some(value as i8 as u8)
+    }
 }
 
 /// Generates the 1 byte PCM samples contained within a sample.
@@ -572,9 +2714,11 @@ impl<'a> Iterator for SampleBytesIter<'a> {
     fn next(&mut self) -> Option<u8> {
         let sample = self.data.get(self.position).cloned();
         self.position += 1;
-        if self.repeat_length != 1 {
+        // A repeat length of 0 or 1 means the sample does not repeat
+        if self.repeat_length > 1 {
             // this sample repeats
-            if self.position >= usize::from(self.repeat_point + self.repeat_length) * 2 {
+            let loop_end = (usize::from(self.repeat_point) + usize::from(self.repeat_length)) * 2;
+            if self.position >= loop_end {
                 self.position = usize::from(self.repeat_point) * 2;
             }
         }
@@ -595,7 +2739,7 @@ impl<'a> core::iter::Iterator for SampleIter<'a> {
     type Item = Sample<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.sample_no <= 31 {
+        if self.sample_no <= self.parent.sample_count() {
             let sample = Sample::new(self.sample_no, self.file_offset, self.parent);
             self.sample_no += 1;
             self.file_offset += sample.sample_length_bytes();
@@ -604,19 +2748,25 @@ impl<'a> core::iter::Iterator for SampleIter<'a> {
             None
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining =
+            usize::from(self.parent.sample_count() + 1).saturating_sub(usize::from(self.sample_no));
+        (remaining, Some(remaining))
+    }
 }
 
+impl<'a> core::iter::ExactSizeIterator for SampleIter<'a> {}
+
 /// Represents a fixed-point 24.8 bit value
 ///
 /// Useful for calculating sample indicies.
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub struct Fractional {
     inner: u32,
 }
 
 impl Fractional {
-    const AMIGA_CLOCK: u32 = 3_546_895;
-
     /// Create a new fractional value
     pub const fn new(value: u32) -> Fractional {
         Fractional { inner: value << 8 }
@@ -625,7 +2775,15 @@ impl Fractional {
     /// Create a new fractional value from the Amiga clock rate
     pub const fn new_from_sample_rate(sample_rate: u32) -> Fractional {
         Fractional {
-            inner: (Self::AMIGA_CLOCK * 256) / sample_rate,
+            inner: (PAL_CLOCK * 256) / sample_rate,
+        }
+    }
+
+    /// Create a new fractional value from a whole part and a fraction, in
+    /// 256ths.
+    pub const fn from_parts(whole: u32, fraction: u8) -> Fractional {
+        Fractional {
+            inner: (whole << 8) | fraction as u32,
         }
     }
 
@@ -634,12 +2792,59 @@ impl Fractional {
         (self.inner >> 8) as usize
     }
 
+    /// The fractional part, in 256ths
+    pub const fn fraction(self) -> u8 {
+        self.inner as u8
+    }
+
+    /// Bring a sample position which has run off the end of a loop back
+    /// round into the loop.
+    ///
+    /// Both arguments are in bytes. Positions before the end of the loop are
+    /// left alone. Any overshoot is kept, fractional part included, so the
+    /// sample plays at a steady pitch through the loop point.
+    pub const fn wrap_loop(self, loop_start: usize, loop_length: usize) -> Fractional {
+        let start = Fractional::new(loop_start as u32).inner;
+        let length = Fractional::new(loop_length as u32).inner;
+        if length == 0 || self.inner < start + length {
+            return self;
+        }
+        Fractional {
+            inner: start + (self.inner - start) % length,
+        }
+    }
+
+    /// Add `step` to this value `count` times over.
+    ///
+    /// Saturates rather than overflowing.
+    pub fn advance_by(&mut self, step: Fractional, count: u32) {
+        self.inner = self.inner.saturating_add(step.inner.saturating_mul(count));
+    }
+
+    /// Multiply this fractional value by a whole number, saturating if the
+    /// result is too big.
+    pub const fn mul_u32(self, factor: u32) -> Fractional {
+        Fractional {
+            inner: self.inner.saturating_mul(factor),
+        }
+    }
+
     /// Divide this fractional value by the given period
+    ///
+    /// Panics if the period is zero - see [`Fractional::apply_period_checked`].
     pub fn apply_period(self, period: u16) -> Fractional {
         Fractional {
             inner: self.inner / u32::from(period),
         }
     }
+
+    /// Divide this fractional value by the given period, unless the period
+    /// is zero.
+    pub fn apply_period_checked(self, period: u16) -> Option<Fractional> {
+        self.inner
+            .checked_div(u32::from(period))
+            .map(|inner| Fractional { inner })
+    }
 }
 
 impl core::ops::Add for Fractional {
@@ -658,4 +2863,21 @@ impl core::ops::AddAssign for Fractional {
     }
 }
 
+/// Subtraction stops at zero, as a sample position cannot be negative.
+impl core::ops::Sub for Fractional {
+    type Output = Fractional;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Fractional {
+            inner: self.inner.saturating_sub(rhs.inner),
+        }
+    }
+}
+
+impl core::ops::SubAssign for Fractional {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.inner = self.inner.saturating_sub(rhs.inner);
+    }
+}
+
 // End of file
@@ -0,0 +1,222 @@
+//! Mixes sample data into stereo audio
+//!
+//! [`Renderer`](crate::Renderer) plays whole modules, and uses a [`Mixer`]
+//! for the last step: taking whatever each channel is playing right now, and
+//! turning it into interleaved stereo samples. Players which track their own
+//! notes and effects can use a [`Mixer`] directly.
+
+use crate::Fractional;
+
+/// What one channel is playing, for a [`Mixer`].
+#[derive(Debug, Clone, Default)]
+pub struct MixerChannel<'a> {
+    /// The sample data, as signed 8-bit PCM
+    pub data: &'a [u8],
+    /// Where the loop starts and how long it is, in bytes, if the sample
+    /// loops
+    pub repeat: Option<(usize, usize)>,
+    /// The Amiga period to play the sample at. A period of zero means the
+    /// channel is silent.
+    pub period: u16,
+    /// The volume, from 0 to 64
+    pub volume: u8,
+    /// How far through the sample data we are
+    pub position: Fractional,
+}
+
+impl MixerChannel<'_> {
+    /// Is this channel making any sound?
+    fn is_playing(&self) -> bool {
+        self.period != 0 && !self.data.is_empty()
+    }
+
+    /// Move on by `step`, `count` times over, then loop back, or stop if we
+    /// have run off the end of a sample which does not loop.
+    fn advance(&mut self, step: Fractional, count: u32) {
+        self.position.advance_by(step, count);
+        match self.repeat {
+            Some((start, length)) => {
+                self.position = self.position.wrap_loop(start, length);
+            }
+            None if self.position.as_index() >= self.data.len() => {
+                self.period = 0;
+            }
+            None => {}
+        }
+    }
+}
+
+/// Mixes [`MixerChannel`]s into interleaved stereo samples.
+///
+/// Channels are panned as on the Amiga - left, right, right, left - and
+/// modules with more than four channels repeat that pattern.
+#[derive(Debug, Clone)]
+pub struct Mixer {
+    clock_ticks_per_device_sample: Fractional,
+    /// The mix for each side is divided by this, to avoid clipping
+    headroom: i32,
+    /// State for the dither noise generator, if dither is enabled
+    dither: Option<u32>,
+    /// Whether to turn down channels playing too fast for the sample rate
+    antialias: bool,
+}
+
+impl Mixer {
+    /// Two channels feed each side, so halving the mix means that even two
+    /// full-volume channels cannot clip.
+    pub const DEFAULT_HEADROOM: u8 = 2;
+
+    /// Any non-zero value will do.
+    const DITHER_SEED: u32 = 0x1234_5678;
+
+    /// Make a new mixer, at the given sample rate.
+    ///
+    /// A sample rate of zero is treated as 1 Hz.
+    pub fn new(sample_rate: u32) -> Mixer {
+        Mixer {
+            clock_ticks_per_device_sample: Fractional::new_from_sample_rate(sample_rate.max(1)),
+            headroom: i32::from(Self::DEFAULT_HEADROOM),
+            dither: None,
+            antialias: false,
+        }
+    }
+
+    /// Set how much the mix is attenuated by, to leave headroom.
+    ///
+    /// The mix for each side is divided by `divisor` before being clipped to
+    /// 16 bits. The default is [`Mixer::DEFAULT_HEADROOM`]. A value of 1
+    /// gives the loudest output, but loud passages may clip. A value of 0 is
+    /// treated as 1.
+    pub fn set_headroom(&mut self, divisor: u8) {
+        self.headroom = i32::from(divisor.max(1));
+    }
+
+    /// Turn dither on or off. It is off by default.
+    ///
+    /// With dither on, triangular (TPDF) noise of up to one bit is added to
+    /// the mix before it is scaled down to 16 bits. This trades the
+    /// distortion you get from rounding quiet sounds for a very low level of
+    /// hiss.
+    pub fn set_dither(&mut self, enabled: bool) {
+        self.dither = if enabled {
+            Some(Self::DITHER_SEED)
+        } else {
+            None
+        };
+    }
+
+    /// Turn the anti-alias guard on or off. It is off by default, as that is
+    /// how an Amiga sounds.
+    ///
+    /// If a note is played at a high enough pitch that we have to step
+    /// through the sample faster than one sample byte per output sample, any
+    /// high frequencies in the sample will alias and screech. With the guard
+    /// on, such channels are turned down in proportion to how fast they are
+    /// stepping, so a channel stepping two bytes at a time plays at half
+    /// volume.
+    pub fn set_antialias(&mut self, enabled: bool) {
+        self.antialias = enabled;
+    }
+
+    /// Fill a buffer with interleaved stereo samples (left, then right).
+    ///
+    /// Each channel moves on by one sample for each stereo pair. A channel
+    /// which reaches the end of a sample which does not loop has its period
+    /// set to zero. If the buffer has an odd length, the last value is left
+    /// alone.
+    pub fn fill(&mut self, channels: &mut [MixerChannel<'_>], buffer: &mut [i16]) {
+        for frame in buffer.chunks_exact_mut(2) {
+            let (left, right) = self.next_frame(channels);
+            frame[0] = left;
+            frame[1] = right;
+        }
+    }
+
+    /// Mix one stereo pair, and move every channel on by one sample.
+    pub fn next_frame(&mut self, channels: &mut [MixerChannel<'_>]) -> (i16, i16) {
+        self.mix(channels.iter_mut())
+    }
+
+    /// Mix one stereo pair from the given channels, in channel order, and
+    /// move each of them on by one sample.
+    pub(crate) fn mix<'a: 'c, 'c>(
+        &mut self,
+        channels: impl Iterator<Item = &'c mut MixerChannel<'a>>,
+    ) -> (i16, i16) {
+        let mut left_sample = 0;
+        let mut right_sample = 0;
+        for (ch_idx, ch) in channels.enumerate() {
+            if !ch.is_playing() {
+                continue;
+            }
+            let sample_byte = ch.data.get(ch.position.as_index()).cloned();
+            // max channel vol (64), sample range [-128,127] scaled to
+            // [-32768, 32767] - we divide by 64 later, once mixed
+            let mut channel_value =
+                i32::from(sample_byte.unwrap_or_default() as i8) * 256 * i32::from(ch.volume);
+            // move the sample index by a non-integer amount
+            let step = self.step(ch.period);
+            // the step in 256ths of a sample byte
+            let step_256ths = (step.as_index() as i32 * 256) + i32::from(step.fraction());
+            if self.antialias && step_256ths > 256 {
+                channel_value = (channel_value * 256) / step_256ths;
+            }
+            ch.advance(step, 1);
+
+            if ch_idx % 4 == 0 || ch_idx % 4 == 3 {
+                left_sample += channel_value;
+            } else {
+                right_sample += channel_value;
+            }
+        }
+
+        let divisor = 64 * self.headroom;
+        let left_sample = self.scale_down(left_sample, divisor);
+        let right_sample = self.scale_down(right_sample, divisor);
+
+        (
+            left_sample.clamp(-32768, 32767) as i16,
+            right_sample.clamp(-32768, 32767) as i16,
+        )
+    }
+
+    /// Move the given channels on by `count` samples, without mixing them.
+    pub(crate) fn skip<'a: 'c, 'c>(
+        &self,
+        channels: impl Iterator<Item = &'c mut MixerChannel<'a>>,
+        count: u32,
+    ) {
+        for ch in channels.filter(|ch| ch.is_playing()) {
+            ch.advance(self.step(ch.period), count);
+        }
+    }
+
+    /// How far through the sample data one output sample takes us, at the
+    /// given period.
+    fn step(&self, period: u16) -> Fractional {
+        self.clock_ticks_per_device_sample
+            .apply_period_checked(period)
+            .unwrap_or_default()
+    }
+
+    /// Divide down a mixed value, adding dither if it is enabled.
+    fn scale_down(&mut self, value: i32, divisor: i32) -> i32 {
+        let Some(state) = self.dither.as_mut() else {
+            return value / divisor;
+        };
+        let mut random = || {
+            // xorshift32
+            *state ^= *state << 13;
+            *state ^= *state >> 17;
+            *state ^= *state << 5;
+            (*state % divisor as u32) as i32
+        };
+        // The difference of two uniform values has a triangular distribution
+        let noise = random() - random();
+        // Round to nearest, rather than towards zero, so the noise averages
+        // out to the true value
+        (value + noise + divisor / 2).div_euclid(divisor)
+    }
+}
+
+// End of file
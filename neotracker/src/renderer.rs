@@ -0,0 +1,667 @@
+//! Turns a Pro Tracker module into audio
+//!
+//! This code is based on https://www.codeslow.com/2019/02/in-this-post-we-will-finally-have-some.html?m=1
+
+use crate::{
+    apply_volume_slide, shift_period_finetuned, sqrt, Effect, EffectTiming, ExtendedEffect,
+    Fractional, Mixer, MixerChannel, Note, ProTrackerModule, MAX_CHANNELS,
+};
+
+/// The state of one channel of the mixer
+#[derive(Default, Clone)]
+struct Channel<'a> {
+    /// Which sample is assigned to this channel (zero if none)
+    sample_num: u8,
+    /// The sample data, pitch and volume, for the mixer
+    voice: MixerChannel<'a>,
+    /// The finetune of the assigned sample, from -8 to 7
+    finetune: i8,
+    effect: Option<Effect>,
+    /// A note waiting for a Note Delay (0xEDx) effect, and the tick it
+    /// should start on
+    delayed_note: Option<(u8, Note)>,
+}
+
+impl<'a> Channel<'a> {
+    /// Start playing a note on this channel.
+    fn trigger(&mut self, modfile: &ProTrackerModule<'a>, note: &Note) {
+        // Do we have a new sample to play?
+        if note.is_empty() {
+            return;
+        }
+        if let Some(sample) = modfile.sample(note.sample_no()) {
+            if note.has_pitch() {
+                self.voice.period = note.period();
+            }
+            self.sample_num = note.sample_no();
+            let sample_length = sample.sample_length_bytes();
+            // A loop which runs past the end of the sample is cut short there
+            let repeat_point = sample.repeat_point_bytes().min(sample_length);
+            let repeat_length = sample
+                .repeat_length_bytes()
+                .min(sample_length - repeat_point);
+            self.voice = MixerChannel {
+                data: modfile.sample_pcm(&sample),
+                repeat: (sample.loops() && repeat_length > 0)
+                    .then_some((repeat_point, repeat_length)),
+                volume: sample.volume(),
+                position: Fractional::default(),
+                ..self.voice
+            };
+            self.finetune = sample.finetune();
+        } else if note.has_pitch() {
+            // A new pitch with no sample number re-triggers
+            // whichever sample this channel is already playing.
+            self.voice.period = note.period();
+            self.voice.position = Fractional::default();
+        }
+    }
+}
+
+/// How many ticks a Note Delay (0xEDx) effect holds this note back for, if
+/// it has one.
+fn note_delay(note: &Note) -> Option<u8> {
+    match note.effect() {
+        Some(Effect::Extended(ExtendedEffect::NoteDelay(ticks))) => Some(ticks),
+        _ => None,
+    }
+}
+
+/// Statistics about a rendered song.
+///
+/// Generated by [`Renderer::render_and_measure()`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Measurement {
+    /// The largest absolute sample value on the left
+    pub peak_left: u16,
+    /// The largest absolute sample value on the right
+    pub peak_right: u16,
+    /// The root-mean-square sample value on the left
+    pub rms_left: f32,
+    /// The root-mean-square sample value on the right
+    pub rms_right: f32,
+    /// How alike the two sides are, from -1.0 (inverted) through 0.0
+    /// (unrelated) to 1.0 (mono). Silence gives 0.0.
+    pub correlation: f32,
+    /// How many stereo sample pairs were rendered
+    pub samples: u64,
+}
+
+/// Things that happen during playback, which a user interface might want
+/// to show.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderEvent {
+    /// A new line has started playing
+    LineChanged {
+        /// The song position being played
+        position: u8,
+        /// The line within the pattern
+        line: u8,
+    },
+    /// We have moved to a new song position
+    PatternChanged {
+        /// The song position being played
+        position: u8,
+        /// The pattern at that song position
+        pattern: u8,
+    },
+    /// The song has finished
+    SongEnded,
+}
+
+/// A fixed-size queue of [`RenderEvent`]s, which drops the oldest event
+/// when full.
+#[derive(Default, Clone)]
+struct EventQueue {
+    events: [Option<RenderEvent>; EventQueue::CAPACITY],
+    /// Where the oldest event is
+    head: usize,
+    /// How many events are queued
+    len: usize,
+}
+
+impl EventQueue {
+    const CAPACITY: usize = 8;
+
+    fn push(&mut self, event: RenderEvent) {
+        let tail = (self.head + self.len) % Self::CAPACITY;
+        self.events[tail] = Some(event);
+        if self.len == Self::CAPACITY {
+            // We just overwrote the oldest event
+            self.head = (self.head + 1) % Self::CAPACITY;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<RenderEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % Self::CAPACITY;
+        self.len -= 1;
+        event
+    }
+}
+
+/// A format that the renderer can write audio out in.
+///
+/// See [`Renderer::fill`].
+pub trait OutputSample: Copy {
+    /// Convert from a 16-bit sample
+    fn from_i16(value: i16) -> Self;
+}
+
+impl OutputSample for i16 {
+    fn from_i16(value: i16) -> Self {
+        value
+    }
+}
+
+impl OutputSample for i8 {
+    /// Keeps the top eight bits
+    fn from_i16(value: i16) -> Self {
+        (value >> 8) as i8
+    }
+}
+
+impl OutputSample for f32 {
+    /// Scales to the range `-1.0..1.0`
+    fn from_i16(value: i16) -> Self {
+        f32::from(value) / 32768.0
+    }
+}
+
+/// Plays a [`ProTrackerModule`], producing stereo 16-bit samples.
+///
+/// Channels 0 and 3 are sent to the left, and channels 1 and 2 to the right,
+/// like on an Amiga. Modules with more than four channels repeat that pattern,
+/// so channels 4 and 7 go left, 5 and 6 go right, and so on. The channels
+/// are mixed by a [`Mixer`].
+#[derive(Clone)]
+pub struct Renderer<'a> {
+    modfile: ProTrackerModule<'a>,
+    /// How many samples left in this tick
+    samples_left: u32,
+    /// How many ticks left in this line
+    ticks_left: u32,
+    ticks_per_line: u32,
+    samples_per_tick: u32,
+    sample_rate: u32,
+    mixer: Mixer,
+    /// The song position of the next line to play
+    position: u8,
+    /// The next line to play
+    line: u8,
+    /// The song position and line currently playing
+    current: (u8, u8),
+    /// One bit per song position we have played
+    positions_played: u128,
+    finished: bool,
+    /// This is set when we get a Pattern Break (0xDxx) effect. It causes
+    /// us to jump to a specific row in the next pattern.
+    pattern_break: Option<u8>,
+    /// This is set when we get a Position Jump (0xBxx) effect. It causes
+    /// us to jump to a specific song position after this line.
+    position_jump: Option<u8>,
+    channels: [Channel<'a>; MAX_CHANNELS],
+    /// How many of `channels` the module actually uses
+    num_channels: usize,
+    events: EventQueue,
+}
+
+impl<'a> Renderer<'a> {
+    /// The headroom used unless you call [`Renderer::set_headroom`].
+    pub const DEFAULT_HEADROOM: u8 = Mixer::DEFAULT_HEADROOM;
+
+    /// Make a new renderer, at the given sample rate.
+    ///
+    /// Each tick needs at least one sample, so rates below 50 Hz are treated
+    /// as 50 Hz.
+    pub fn new(modfile: ProTrackerModule<'a>, sample_rate: u32) -> Renderer<'a> {
+        let sample_rate = sample_rate.max(50);
+        Renderer {
+            num_channels: modfile.channel_count(),
+            modfile,
+            samples_left: 0,
+            ticks_left: 0,
+            ticks_per_line: 6,
+            samples_per_tick: sample_rate / 50,
+            sample_rate,
+            mixer: Mixer::new(sample_rate),
+            position: 0,
+            line: 0,
+            current: (0, 0),
+            positions_played: 0,
+            finished: false,
+            pattern_break: None,
+            position_jump: None,
+            channels: core::array::from_fn(|_| Channel::default()),
+            events: EventQueue::default(),
+        }
+    }
+
+    /// Get the module being played
+    pub fn modfile(&self) -> &ProTrackerModule<'a> {
+        &self.modfile
+    }
+
+    /// Which song position is currently playing
+    pub fn position(&self) -> u8 {
+        self.current.0
+    }
+
+    /// Which line of the current pattern is playing
+    pub fn line(&self) -> u8 {
+        self.current.1
+    }
+
+    /// Has the song finished?
+    ///
+    /// A song finishes when it runs out of song positions, or when it jumps
+    /// back to a position it has already played (as it would otherwise loop
+    /// forever).
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// How many more stereo sample pairs will be produced before the song
+    /// ends.
+    ///
+    /// This works through the rest of the song, following speed changes,
+    /// jumps and breaks, so it is not free. Returns `None` if the song does
+    /// not look like it will ever end.
+    pub fn frames_remaining(&self) -> Option<u64> {
+        // Enough to play every line of every position, with room to spare
+        const MAX_LINES: u32 = 128 * 64 * 2;
+        let samples_per_tick = u64::from(self.samples_per_tick);
+        let mut future = self.clone();
+        let mut frames =
+            u64::from(future.samples_left) + u64::from(future.ticks_left) * samples_per_tick;
+        for _ in 0..MAX_LINES {
+            if !future.start_line() {
+                return Some(frames);
+            }
+            frames += u64::from(future.ticks_per_line) * samples_per_tick;
+            // Per-tick effects can't change the timing, so skip them
+            future.ticks_left = 0;
+        }
+        None
+    }
+
+    /// Set how much the mix is attenuated by, to leave headroom.
+    ///
+    /// See [`Mixer::set_headroom`].
+    pub fn set_headroom(&mut self, divisor: u8) {
+        self.mixer.set_headroom(divisor);
+    }
+
+    /// Play faster or slower than the module says, without changing pitch.
+    ///
+    /// A multiplier of 2.0 plays at double speed, and 0.5 at half speed.
+    /// This applies on top of any speed changes in the module itself. Values
+    /// which are not positive numbers are ignored.
+    pub fn set_speed_multiplier(&mut self, multiplier: f32) {
+        if multiplier > 0.0 && multiplier.is_finite() {
+            let samples_per_tick = self.sample_rate as f32 / 50.0 / multiplier;
+            self.samples_per_tick = (samples_per_tick as u32).max(1);
+        }
+    }
+
+    /// Turn dither on or off. It is off by default.
+    ///
+    /// See [`Mixer::set_dither`].
+    pub fn set_dither(&mut self, enabled: bool) {
+        self.mixer.set_dither(enabled);
+    }
+
+    /// Turn the anti-alias guard on or off. It is off by default, as that is
+    /// how an Amiga sounds.
+    ///
+    /// See [`Mixer::set_antialias`].
+    pub fn set_antialias(&mut self, enabled: bool) {
+        self.mixer.set_antialias(enabled);
+    }
+
+    /// How many channels are currently producing sound
+    pub fn active_channels(&self) -> usize {
+        self.channels[..self.num_channels]
+            .iter()
+            .filter(|ch| ch.sample_num != 0 && ch.voice.period != 0)
+            .count()
+    }
+
+    /// The effect currently running on a channel, for debugging.
+    ///
+    /// Only effects which do something on every tick (arpeggio, slides and so
+    /// on) are remembered - one-off effects like Set Volume are applied when
+    /// the line is loaded and are not reported here. Returns `None` for an
+    /// out-of-range channel.
+    pub fn channel_effect(&self, ch: usize) -> Option<Effect> {
+        self.channels[..self.num_channels]
+            .get(ch)
+            .and_then(|ch| ch.effect)
+    }
+
+    /// The period of the note currently playing on a channel, or `None` for
+    /// an out-of-range channel.
+    pub fn channel_period(&self, ch: usize) -> Option<u16> {
+        self.channels[..self.num_channels]
+            .get(ch)
+            .map(|ch| ch.voice.period)
+    }
+
+    /// The sample number each channel is currently playing, or 0 for none.
+    ///
+    /// Only the first four channels are reported.
+    pub fn channel_sample_numbers(&self) -> [u8; 4] {
+        let mut numbers = [0; 4];
+        for (number, ch) in numbers.iter_mut().zip(&self.channels[..self.num_channels]) {
+            *number = ch.sample_num;
+        }
+        numbers
+    }
+
+    /// Fill a buffer with interleaved stereo samples (left, then right), in
+    /// whichever format you need.
+    ///
+    /// If the buffer has an odd length, the last entry is left alone.
+    pub fn fill<T: OutputSample>(&mut self, buffer: &mut [T]) {
+        for frame in buffer.chunks_exact_mut(2) {
+            let (left, right) = self.next_sample();
+            frame[0] = T::from_i16(left);
+            frame[1] = T::from_i16(right);
+        }
+    }
+
+    /// Return a stereo sample pair
+    pub fn next_sample(&mut self) -> (i16, i16) {
+        if self.ticks_left == 0 && self.samples_left == 0 {
+            // It is time for a new line
+            if !self.start_line() {
+                return (0, 0);
+            }
+        } else if self.samples_left == 0 {
+            // end of a tick
+            self.samples_left = self.samples_per_tick - 1;
+            self.ticks_left -= 1;
+            self.apply_tick_effects();
+        } else {
+            // just another sample
+            self.samples_left -= 1;
+        }
+
+        let voices = self.channels[..self.num_channels]
+            .iter_mut()
+            .map(|ch| &mut ch.voice);
+        self.mixer.mix(voices)
+    }
+
+    /// Skip over the rest of the current line, and then `n` more lines,
+    /// without producing any audio.
+    ///
+    /// Speed changes, pattern breaks and position jumps are all processed as
+    /// normal, so playback continues from the right place and at the right
+    /// speed.
+    pub fn advance_lines(&mut self, n: u32) {
+        self.finish_line();
+        for _ in 0..n {
+            if !self.start_line() {
+                break;
+            }
+            // This is the sample that next_sample() would have mixed
+            self.skip_samples(1);
+            self.finish_line();
+        }
+        self.samples_left = 0;
+    }
+
+    /// Play the rest of the song, measuring the levels of the output.
+    ///
+    /// The audio is thrown away. This runs until [`Renderer::finished`]
+    /// returns `true`, so it may take a while.
+    pub fn render_and_measure(&mut self) -> Measurement {
+        let mut peak_left = 0;
+        let mut peak_right = 0;
+        let mut sum_left = 0.0;
+        let mut sum_right = 0.0;
+        let mut sum_product = 0.0;
+        let mut samples = 0;
+        while !self.finished {
+            let (left, right) = self.next_sample();
+            peak_left = peak_left.max(left.unsigned_abs());
+            peak_right = peak_right.max(right.unsigned_abs());
+            let (left, right) = (f64::from(left), f64::from(right));
+            sum_left += left * left;
+            sum_right += right * right;
+            sum_product += left * right;
+            samples += 1;
+        }
+        let mean = |sum: f64| {
+            if samples == 0 {
+                0.0
+            } else {
+                sum / samples as f64
+            }
+        };
+        let energy = sqrt(sum_left * sum_right);
+        Measurement {
+            peak_left,
+            peak_right,
+            rms_left: sqrt(mean(sum_left)) as f32,
+            rms_right: sqrt(mean(sum_right)) as f32,
+            correlation: if energy == 0.0 {
+                0.0
+            } else {
+                (sum_product / energy) as f32
+            },
+            samples,
+        }
+    }
+
+    /// Fetch the oldest event that has not yet been collected.
+    ///
+    /// Events are queued as the song plays, so call this until it returns
+    /// `None` between each batch of samples. If the queue fills up, the
+    /// oldest events are dropped.
+    pub fn poll_event(&mut self) -> Option<RenderEvent> {
+        self.events.pop()
+    }
+
+    /// Mark the song as finished.
+    fn end_song(&mut self) {
+        self.finished = true;
+        self.events.push(RenderEvent::SongEnded);
+    }
+
+    /// Run the remaining ticks of the current line.
+    fn finish_line(&mut self) {
+        self.skip_samples(self.samples_left);
+        while self.ticks_left > 0 {
+            self.ticks_left -= 1;
+            self.apply_tick_effects();
+            self.skip_samples(self.samples_per_tick);
+        }
+    }
+
+    /// Move every channel on by `count` samples, without mixing them.
+    fn skip_samples(&mut self, count: u32) {
+        let voices = self.channels[..self.num_channels]
+            .iter_mut()
+            .map(|ch| &mut ch.voice);
+        self.mixer.skip(voices, count);
+    }
+
+    /// Load the next line, and apply any effects it has.
+    ///
+    /// Returns false if the song has finished.
+    fn start_line(&mut self) -> bool {
+        if self.finished {
+            return false;
+        }
+
+        // Did we have a position jump or pattern break? Jump straight there.
+        if self.position_jump.is_some() || self.pattern_break.is_some() {
+            let position = self
+                .position_jump
+                .take()
+                .unwrap_or(self.current.0.wrapping_add(1));
+            if position < 128 && self.positions_played & (1 << position) != 0 {
+                // We've been here before, so this song loops
+                self.end_song();
+                return false;
+            }
+            self.position = position;
+            self.line = self.pattern_break.take().unwrap_or(0);
+        }
+
+        // Find which line we play next. It might be the next line in this
+        // pattern, or it might be the first line in the next pattern.
+        let line = loop {
+            // Work out which pattern we're playing
+            let Some(pattern_idx) = self.modfile.song_position(self.position) else {
+                self.end_song();
+                return false;
+            };
+            // Grab the pattern
+            let Some(pattern) = self.modfile.pattern(pattern_idx) else {
+                self.end_song();
+                return false;
+            };
+            // Get the line from the pattern
+            let Some(line) = pattern.line(self.line) else {
+                // Go to start of next pattern
+                self.line = 0;
+                self.position += 1;
+                continue;
+            };
+            // There was no need to go the next pattern, so produce this
+            // line from the loop.
+            break line;
+        };
+
+        if self.positions_played == 0 || self.current.0 != self.position {
+            if let Some(pattern) = self.modfile.song_position(self.position) {
+                self.events.push(RenderEvent::PatternChanged {
+                    position: self.position,
+                    pattern,
+                });
+            }
+        }
+        self.events.push(RenderEvent::LineChanged {
+            position: self.position,
+            line: self.line,
+        });
+        self.current = (self.position, self.line);
+        self.positions_played |= 1 << self.position;
+
+        // Load the channels with new line data
+        for (ch, note) in self.channels.iter_mut().zip(line.channels()) {
+            ch.delayed_note = None;
+            match note_delay(note) {
+                Some(ticks) if ticks > 0 => {
+                    // Hold the note back until the given tick. A delay on a
+                    // cell with no note in it does nothing.
+                    if note.triggers_sample() || note.has_pitch() {
+                        ch.delayed_note = Some((ticks, *note));
+                    }
+                }
+                _ => {
+                    ch.trigger(&self.modfile, note);
+                }
+            }
+            ch.effect = None;
+            match note.effect() {
+                Some(e) if e.timing() == EffectTiming::PerTick => {
+                    // Nothing happens on the first tick - we'll need this
+                    // for the ticks that follow
+                    ch.effect = Some(e);
+                }
+                Some(Effect::SetVolume(value)) => {
+                    ch.voice.volume = value.min(64);
+                }
+                Some(Effect::SetSpeed(value)) if (1..=31).contains(&value) => {
+                    self.ticks_per_line = u32::from(value);
+                }
+                Some(Effect::SetSpeed(_)) => {
+                    // Either zero, which Pro Tracker ignores, or they
+                    // are trying to set speed in beats per minute
+                }
+                Some(Effect::SampleOffset(n)) => {
+                    let offset = u32::from(n) * 256;
+                    ch.voice.position = Fractional::new(offset);
+                }
+                Some(Effect::PatternBreak(row)) => {
+                    // Start the next pattern early, at the given row. The
+                    // row is given as two decimal digits.
+                    self.pattern_break = Some((row >> 4) * 10 + (row & 0x0F));
+                }
+                Some(Effect::PositionJump(position)) => {
+                    self.position_jump = Some(position);
+                }
+                _ => {
+                    // Not supported yet
+                }
+            }
+        }
+
+        self.line += 1;
+        self.samples_left = self.samples_per_tick - 1;
+        self.ticks_left = self.ticks_per_line - 1;
+        true
+    }
+
+    /// Apply the effects that change something on every tick.
+    fn apply_tick_effects(&mut self) {
+        let lower_third = self.ticks_per_line / 3;
+        let upper_third = lower_third * 2;
+        let tick = self.ticks_per_line - 1 - self.ticks_left;
+        for ch in self.channels[..self.num_channels].iter_mut() {
+            if let Some((delay, note)) = ch.delayed_note {
+                if u32::from(delay) == tick {
+                    ch.delayed_note = None;
+                    ch.trigger(&self.modfile, &note);
+                }
+            }
+            match ch.effect {
+                Some(Effect::Arpeggio(n)) => {
+                    if self.ticks_left == upper_third {
+                        let half_steps = n >> 4;
+                        if let Some(new_period) =
+                            shift_period_finetuned(ch.voice.period, half_steps, ch.finetune)
+                        {
+                            ch.voice.period = new_period;
+                        }
+                    } else if self.ticks_left == lower_third {
+                        let first_half_steps = n >> 4;
+                        let second_half_steps = n & 0x0F;
+                        if let Some(new_period) = shift_period_finetuned(
+                            ch.voice.period,
+                            second_half_steps.wrapping_sub(first_half_steps),
+                            ch.finetune,
+                        ) {
+                            ch.voice.period = new_period;
+                        }
+                    }
+                }
+                Some(Effect::SlideUp(n)) => {
+                    ch.voice.period = ch.voice.period.saturating_sub(u16::from(n));
+                }
+                Some(Effect::SlideDown(n)) => {
+                    ch.voice.period = ch.voice.period.saturating_add(u16::from(n));
+                }
+                Some(Effect::VolumeSlide { up, down }) => {
+                    // Sliding up takes priority
+                    let delta = if up > 0 { up as i8 } else { -(down as i8) };
+                    ch.voice.volume = apply_volume_slide(ch.voice.volume, delta);
+                }
+                _ => {
+                    // do nothing
+                }
+            }
+        }
+    }
+}
+
+// End of file
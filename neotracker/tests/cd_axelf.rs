@@ -9,6 +9,21 @@ fn recognise_file() {
     let _pt = neotracker::ProTrackerModule::new(DATA).unwrap();
 }
 
+#[test]
+fn probe_file() {
+    assert_eq!(
+        neotracker::probe(DATA),
+        Some(neotracker::Format::ProTracker4)
+    );
+    assert_eq!(neotracker::probe(&DATA[0..1000]), None);
+}
+
+#[test]
+fn song_name() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    assert_eq!(pt.song_name(), b"axel.f-theme");
+}
+
 #[test]
 fn sample_names() {
     static SAMPLE_NAMES: &[&[u8]] = &[
@@ -92,7 +107,7 @@ fn sample_names() {
 
 #[test]
 fn sample_properties() {
-    static SAMPLE_PROPERTIES: &[(usize, u8, u8, usize, usize)] = &[
+    static SAMPLE_PROPERTIES: &[(usize, i8, u8, usize, usize)] = &[
         // Sample 0
         (0, 0, 0, 0, 0),
         // Sample 1
@@ -202,18 +217,17 @@ fn decode_song() {
         .unwrap();
     }
 
-    for pattern in pt.song_positions() {
-        writeln!(buffer, "Pattern {}", pattern).unwrap();
-        let pattern = pt.pattern(*pattern).unwrap();
+    for pattern in pt.song_patterns() {
+        writeln!(buffer, "Pattern {}", pattern.pattern_no()).unwrap();
         for line in pattern.lines() {
             write!(buffer, "\t|").unwrap();
             for ch in 0..4 {
                 write!(
                     buffer,
                     " {:02x} {:06} {:04x} |",
-                    line.channel[ch].sample_no(),
-                    line.channel[ch].period(),
-                    line.channel[ch].effect_u16(),
+                    line.channels()[ch].sample_no(),
+                    line.channels()[ch].period(),
+                    line.channels()[ch].effect_u16(),
                 )
                 .unwrap();
             }
@@ -222,3 +236,424 @@ fn decode_song() {
     }
     assert_eq!(expected, buffer);
 }
+
+#[test]
+fn decode_note() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let line = pt.pattern(0).unwrap().line(0).unwrap();
+    assert_eq!(
+        line.channels()[0].decode(),
+        neotracker::DecodedNote {
+            sample_no: 17,
+            period: 302,
+            note: Some("F2♯"),
+            effect: Some(neotracker::Effect::SetVolume(0x28)),
+        }
+    );
+}
+
+#[test]
+fn trimmed_pcm() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    // A looping sample, where the loop runs to the end of the sample
+    let sample = pt.sample(17).unwrap();
+    assert_eq!(sample.trimmed_pcm().len(), 4430 + 9626);
+    // A one-shot sample
+    let sample = pt.sample(5).unwrap();
+    assert_eq!(sample.trimmed_pcm(), sample.raw_sample_bytes());
+}
+
+#[test]
+fn pattern_grid() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let pattern = pt.pattern(3).unwrap();
+    let grid = pattern.to_grid::<4>();
+    for (row, line) in grid.iter().zip(pattern.lines()) {
+        for (grid_note, note) in row.iter().zip(line.channels().iter()) {
+            assert_eq!(grid_note.decode(), note.decode());
+        }
+    }
+}
+
+#[test]
+fn pattern_array() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let pattern = pt.pattern(3).unwrap();
+    let lines = pattern.to_array();
+    assert_eq!(core::mem::size_of_val(&lines), 1024);
+    assert_eq!(&lines[10], pattern.line(10).unwrap().channels());
+    for (array_line, line) in lines.iter().zip(pattern.lines()) {
+        assert_eq!(array_line, line.channels());
+    }
+}
+
+#[test]
+fn loop_length_frames() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    // Play a C-2 at roughly the rate the Amiga would, so one byte is one frame
+    let sample = pt.sample(17).unwrap();
+    let frames = sample.loop_length_frames(428, 8287);
+    assert!((9600..=9650).contains(&frames), "Got {} frames", frames);
+    // Double the output rate, double the frames
+    let frames = sample.loop_length_frames(428, 8287 * 2);
+    assert!((19200..=19300).contains(&frames), "Got {} frames", frames);
+    // A one-shot sample has no loop
+    let sample = pt.sample(5).unwrap();
+    assert_eq!(sample.loop_length_frames(428, 8287), 0);
+}
+
+#[test]
+fn dialect() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    assert_eq!(pt.likely_dialect(), neotracker::Dialect::ProTracker);
+}
+
+#[test]
+fn loop_iter() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let sample = pt.sample(17).unwrap();
+    let loop_data = &sample.raw_sample_bytes()[4430..4430 + 9626];
+    let two_loops: Vec<u8> = sample.loop_iter().take(9626 * 2).collect();
+    assert_eq!(&two_loops[0..9626], loop_data);
+    assert_eq!(&two_loops[9626..], loop_data);
+    // A one-shot sample has no loop
+    let sample = pt.sample(5).unwrap();
+    assert_eq!(sample.loop_iter().next(), None);
+}
+
+#[test]
+fn positions_using_sample() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let positions: Vec<u8> = pt.positions_using_sample(14).collect();
+    assert_eq!(positions, [8, 9]);
+    let positions: Vec<u8> = pt.positions_using_sample(18).collect();
+    assert_eq!(positions, [6, 7, 14, 15]);
+    // Sample 1 is never played
+    assert_eq!(pt.positions_using_sample(1).next(), None);
+    assert_eq!(pt.positions_using_sample(0).next(), None);
+}
+
+#[test]
+fn layout() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let layout = pt.layout();
+    assert_eq!(layout.title, 0..20);
+    assert_eq!(layout.order_list, 952..1080);
+    assert_eq!(layout.magic, 1080..1084);
+    assert_eq!(&DATA[layout.magic.clone()], b"M.K.");
+    assert_eq!(layout.patterns.start, 1084);
+    assert_eq!(layout.patterns.len() % 1024, 0);
+    // Sample data follows on directly from the patterns
+    assert_eq!(layout.samples[0].start, layout.patterns.end);
+    let sample = pt.sample(17).unwrap();
+    assert_eq!(&DATA[layout.samples[16].clone()], sample.raw_sample_bytes());
+    assert_eq!(layout.samples[30].end, DATA.len());
+}
+
+#[test]
+fn render_and_measure() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    let measurement = renderer.render_and_measure();
+    assert!(renderer.finished());
+    assert!(measurement.samples > 8000);
+    assert!(measurement.peak_left > 0 && measurement.peak_right > 0);
+    assert!(measurement.rms_left > 0.0 && measurement.rms_right > 0.0);
+    assert!(measurement.rms_left < f32::from(measurement.peak_left));
+    assert!(measurement.rms_right < f32::from(measurement.peak_right));
+    assert!((-1.0..=1.0).contains(&measurement.correlation));
+}
+
+#[test]
+fn free_sample_slots() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let free: Vec<u8> = pt.free_sample_slots().collect();
+    assert_eq!(free.len(), 14);
+    assert_eq!(free[0], 13);
+    assert_eq!(&free[1..], (19..=31).collect::<Vec<u8>>());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn pcm_with_fade() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let sample = pt.sample(5).unwrap();
+    let raw = sample.raw_sample_bytes();
+    let faded = sample.pcm_with_fade(100);
+    assert_eq!(faded.len(), raw.len());
+    // Untouched before the fade
+    let split = raw.len() - 100;
+    assert!(faded[..split]
+        .iter()
+        .zip(&raw[..split])
+        .all(|(f, r)| *f == *r as i8));
+    assert_eq!(*faded.last().unwrap(), 0);
+    // Asking for a longer fade than the sample is fine
+    assert_eq!(sample.pcm_with_fade(usize::MAX).len(), raw.len());
+    assert_eq!(
+        sample.pcm_with_fade(0),
+        raw.iter().map(|b| *b as i8).collect::<Vec<i8>>()
+    );
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn extract_pattern_module() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let pattern_no = pt.song_position(0).unwrap();
+    let owned = pt.extract_pattern_module(pattern_no).unwrap();
+    let extracted = owned.module();
+    assert_eq!(extracted.song_positions(), &[0]);
+    assert_eq!(extracted.num_patterns(), 1);
+    // The first pattern plays samples 9, 10, 11 and 17
+    assert_eq!(
+        extracted.sample(17).unwrap().raw_sample_bytes(),
+        pt.sample(17).unwrap().raw_sample_bytes()
+    );
+    assert_eq!(extracted.sample(16).unwrap().sample_length(), 0);
+    assert_eq!(extracted.free_sample_slots().count(), 31 - 4);
+    assert!(pt.extract_pattern_module(pt.num_patterns()).is_none());
+
+    // It should sound just like the start of the song
+    let mut original = neotracker::Renderer::new(pt, 8000);
+    let mut copy = neotracker::Renderer::new(owned.module(), 8000);
+    for _ in 0..(64 * 6 * 160) {
+        assert_eq!(original.next_sample(), copy.next_sample());
+    }
+    copy.next_sample();
+    assert!(copy.finished());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn rebuild() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let rebuilt = pt.rebuild().unwrap();
+    assert_eq!(rebuilt.as_bytes(), DATA);
+}
+
+#[test]
+fn is_short() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    assert!(!pt.is_short());
+}
+
+#[test]
+fn max_simultaneous_notes() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    assert_eq!(pt.max_simultaneous_notes(), 4);
+}
+
+#[test]
+fn sample_info() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    for (sample_no, sample) in (1..=31).zip(pt.samples()) {
+        let info = pt.sample_info(sample_no).unwrap();
+        assert_eq!(info.name(), sample.name());
+        assert_eq!(info.sample_length(), sample.sample_length());
+        assert_eq!(info.finetune(), sample.finetune());
+        assert_eq!(info.volume(), sample.volume());
+        assert_eq!(info.repeat_point(), sample.repeat_point());
+        assert_eq!(info.repeat_length(), sample.repeat_length());
+        assert_eq!(sample.info().name(), info.name());
+    }
+    assert!(pt.sample_info(0).is_none());
+    assert!(pt.sample_info(32).is_none());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn effect_usage() {
+    use neotracker::Effect;
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let usage: Vec<(Effect, u32)> = pt.effect_usage().collect();
+    assert_eq!(usage.len(), 30);
+    assert_eq!(usage[0], (Effect::SetVolume(0x28), 100));
+    assert!(usage.contains(&(Effect::Arpeggio(0x50), 28)));
+    assert!(usage.contains(&(Effect::VolumeSlide { up: 0, down: 15 }, 92)));
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn density_timeline() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let mut total = 0;
+    for pattern_no in pt.song_positions() {
+        for line in pt.pattern(*pattern_no).unwrap().lines() {
+            total += line.channels().iter().filter(|n| n.period() != 0).count() as u32;
+        }
+    }
+    let timeline = pt.density_timeline(16);
+    // 22 positions of 64 lines each
+    assert_eq!(timeline.len(), 22 * 4);
+    assert_eq!(timeline.iter().sum::<u32>(), total);
+    let timeline = pt.density_timeline(100);
+    assert_eq!(timeline.len(), 15);
+    assert_eq!(timeline.iter().sum::<u32>(), total);
+}
+
+#[test]
+fn busiest_pattern_per_channel() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    assert_eq!(
+        pt.busiest_pattern_per_channel(),
+        [Some(7), Some(9), Some(0), Some(2)]
+    );
+}
+
+#[test]
+fn total_sample_bytes() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    assert_eq!(pt.total_sample_bytes(), 158730);
+}
+
+#[test]
+fn validate() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    assert_eq!(pt.validate(), Ok(()));
+}
+
+#[test]
+fn sample_count() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    assert_eq!(pt.format(), neotracker::Format::ProTracker4);
+    assert_eq!(pt.sample_count(), 31);
+    assert_eq!(pt.samples().count(), 31);
+    let mut samples = pt.samples();
+    assert_eq!(samples.len(), 31);
+    samples.next();
+    assert_eq!(samples.len(), 30);
+    assert_eq!(samples.by_ref().skip(29).count(), 1);
+    assert_eq!(samples.len(), 0);
+    assert!(pt.sample_info(31).is_some());
+    assert!(pt.sample_info(32).is_none());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn to_wav() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let sample = pt.sample(5).unwrap();
+    let wav = sample.to_wav(8363);
+    let u16_at = |offset: usize| u16::from_le_bytes([wav[offset], wav[offset + 1]]);
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes([
+            wav[offset],
+            wav[offset + 1],
+            wav[offset + 2],
+            wav[offset + 3],
+        ])
+    };
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(u32_at(4) as usize, wav.len() - 8);
+    assert_eq!(&wav[8..12], b"WAVE");
+    assert_eq!(&wav[12..16], b"fmt ");
+    assert_eq!(u32_at(16), 16);
+    // PCM, mono, 8363 Hz, 8363 bytes/sec, 1 byte per frame, 8 bits
+    assert_eq!(u16_at(20), 1);
+    assert_eq!(u16_at(22), 1);
+    assert_eq!(u32_at(24), 8363);
+    assert_eq!(u32_at(28), 8363);
+    assert_eq!(u16_at(32), 1);
+    assert_eq!(u16_at(34), 8);
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(u32_at(40) as usize, sample.sample_length_bytes());
+    let raw = sample.raw_sample_bytes();
+    assert_eq!(wav[44] as i16 - 128, raw[0] as i8 as i16);
+    assert_eq!(wav[44..].len(), raw.len());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn render_preview() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let preview = pt.render_preview(8000, 2.5);
+    assert_eq!(preview.len(), 2 * 20000);
+    // It is the same as the start of the song
+    let mut renderer = neotracker::Renderer::new(pt.clone(), 8000);
+    for frame in preview.chunks_exact(2) {
+        assert_eq!(renderer.next_sample(), (frame[0], frame[1]));
+    }
+    // A long preview stops at the end of the song
+    let whole_song = pt.render_preview(8000, 3600.0);
+    assert!(whole_song.len() < 3600 * 8000 * 2);
+    assert!(whole_song.len() > preview.len());
+    assert_eq!(pt.render_preview(8000, f32::MAX), whole_song);
+    assert_eq!(pt.render_preview(8000, f32::INFINITY), whole_song);
+    // A silly sample rate gives a short preview, rather than a panic
+    assert!(pt.render_preview(0, 2.5).is_empty());
+    assert_eq!(pt.render_preview(10, 2.5).len(), 2 * 25);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn render_to_wav_file() {
+    let path = std::env::temp_dir().join("neotracker-axelf-render.wav");
+    neotracker::render_to_wav_file(DATA, 8000, &path).unwrap();
+    let wav = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let u32_at = |offset: usize| u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap());
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(&wav[8..12], b"WAVE");
+    assert_eq!(u32_at(4) as usize, wav.len() - 8);
+    assert_eq!(u32_at(40) as usize, wav.len() - 44);
+    // Stereo, 16-bit frames, and the song lasts more than a minute
+    assert_eq!(u32_at(28), 8000 * 4);
+    assert_eq!((wav.len() - 44) % 4, 0);
+    assert!(wav.len() > 60 * 8000 * 4);
+}
+
+#[test]
+fn duration_secs() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt.clone(), 8000);
+    let rendered = renderer.render_and_measure().samples as f32 / 8000.0;
+    let estimate = pt.duration_secs(6, 125);
+    assert!(
+        (estimate - rendered).abs() < 0.1,
+        "{estimate} vs {rendered}"
+    );
+}
+
+#[test]
+fn song_patterns() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    assert_eq!(pt.song_patterns().count(), usize::from(pt.song_length()));
+    for (pattern, pattern_no) in pt.song_patterns().zip(pt.song_positions()) {
+        assert_eq!(pattern.pattern_no(), *pattern_no);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn from_reader() {
+    let mut cursor = std::io::Cursor::new(DATA);
+    let owned = neotracker::ProTrackerModule::from_reader(&mut cursor).unwrap();
+    assert_eq!(owned.as_bytes(), DATA);
+    let pt = owned.module();
+    assert_eq!(pt.song_name(), b"axel.f-theme");
+    assert_eq!(
+        pt.num_patterns(),
+        neotracker::ProTrackerModule::new(DATA)
+            .unwrap()
+            .num_patterns()
+    );
+}
+
+#[test]
+fn peak_amplitude() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    let peak = |sample_no| pt.sample(sample_no).unwrap().peak_amplitude();
+    assert_eq!(peak(1), 0);
+    assert_eq!(peak(5), 128);
+    assert_eq!(peak(9), 107);
+    assert_eq!(peak(10), 117);
+}
+
+#[test]
+fn restart_position() {
+    let pt = neotracker::ProTrackerModule::new(DATA).unwrap();
+    assert_eq!(pt.song_length(), 22);
+    assert_eq!(pt.restart_position(), 127);
+    assert_eq!(pt.restart_position(), DATA[951]);
+}
@@ -0,0 +1,143 @@
+//! Helpers for building small MOD files in memory, for tests that need a
+//! particular pattern or sample layout rather than a real-world file.
+//!
+//! The file itself is written by [`neotracker::ModuleBuilder`]. This just
+//! lets a test fill in any sample slot or pattern cell, in any order.
+
+#![allow(dead_code)]
+
+use neotracker::{Effect, ModuleBuilder, Note};
+
+/// A four channel `M.K.` module under construction.
+pub struct TestModule {
+    title: Vec<u8>,
+    samples: Vec<TestSample>,
+    positions: Vec<u8>,
+    patterns: Vec<[[Note; 4]; 64]>,
+}
+
+/// One entry in the sample table of a [`TestModule`].
+#[derive(Clone, Default)]
+struct TestSample {
+    name: Vec<u8>,
+    pcm: Vec<u8>,
+    finetune: i8,
+    volume: u8,
+    repeat_point: u16,
+    repeat_length: u16,
+}
+
+/// A pattern with every cell empty
+fn empty_pattern() -> [[Note; 4]; 64] {
+    [[Note::new(0, 0, None); 4]; 64]
+}
+
+impl TestModule {
+    /// A module with one empty pattern, played once.
+    pub fn new() -> TestModule {
+        TestModule {
+            title: Vec::new(),
+            samples: vec![
+                TestSample {
+                    repeat_length: 1,
+                    ..Default::default()
+                };
+                31
+            ],
+            positions: vec![0],
+            patterns: vec![empty_pattern()],
+        }
+    }
+
+    /// Set the song title.
+    pub fn set_title(&mut self, title: &[u8]) -> &mut Self {
+        self.title = title.to_vec();
+        self
+    }
+
+    /// Set the song order, adding empty patterns so every entry is valid.
+    pub fn set_positions(&mut self, positions: &[u8]) -> &mut Self {
+        self.positions = positions.to_vec();
+        let highest = positions.iter().cloned().max().unwrap_or(0);
+        while self.patterns.len() <= usize::from(highest) {
+            self.patterns.push(empty_pattern());
+        }
+        self
+    }
+
+    /// Write one cell of a pattern. The effect is given as the 12-bit value
+    /// stored in the file.
+    pub fn set_note(
+        &mut self,
+        pattern: u8,
+        line: u8,
+        channel: u8,
+        sample_no: u8,
+        period: u16,
+        effect: u16,
+    ) -> &mut Self {
+        self.patterns[usize::from(pattern)][usize::from(line)][usize::from(channel)] =
+            Note::new(sample_no, period, Effect::try_from(effect));
+        self
+    }
+
+    /// Fill in a one-shot sample. The PCM must be an even number of bytes.
+    pub fn set_sample(&mut self, sample_no: u8, pcm: &[u8], volume: u8) -> &mut Self {
+        let sample = &mut self.samples[usize::from(sample_no - 1)];
+        sample.pcm = pcm.to_vec();
+        sample.volume = volume;
+        self
+    }
+
+    /// Set the name of a sample.
+    pub fn set_sample_name(&mut self, sample_no: u8, name: &[u8]) -> &mut Self {
+        self.samples[usize::from(sample_no - 1)].name = name.to_vec();
+        self
+    }
+
+    /// Set the loop of a sample, in 16-bit words.
+    pub fn set_sample_loop(
+        &mut self,
+        sample_no: u8,
+        repeat_point: u16,
+        repeat_length: u16,
+    ) -> &mut Self {
+        let sample = &mut self.samples[usize::from(sample_no - 1)];
+        sample.repeat_point = repeat_point;
+        sample.repeat_length = repeat_length;
+        self
+    }
+
+    /// Set the finetune of a sample, as the four bit value stored in the
+    /// file (so `0x0F` is -1).
+    pub fn set_sample_finetune(&mut self, sample_no: u8, finetune: u8) -> &mut Self {
+        self.samples[usize::from(sample_no - 1)].finetune = ((finetune & 0x0F) as i8) << 4 >> 4;
+        self
+    }
+
+    /// Lay the module out as a MOD file.
+    pub fn build(&self) -> Vec<u8> {
+        let mut builder = ModuleBuilder::new();
+        builder.set_title(&self.title);
+        // Add every slot, so each sample keeps its number
+        for sample in self.samples.iter() {
+            builder
+                .add_sample(
+                    &sample.name,
+                    &sample.pcm,
+                    sample.volume,
+                    sample.finetune,
+                    sample.repeat_point,
+                    sample.repeat_length,
+                )
+                .expect("Add sample");
+        }
+        for pattern in self.patterns.iter() {
+            builder.add_pattern(pattern).expect("Add pattern");
+        }
+        builder
+            .set_positions(&self.positions)
+            .expect("Set positions");
+        builder.build()
+    }
+}
@@ -0,0 +1,102 @@
+//! Tests for effect decoding and the effect helper functions.
+
+#[test]
+fn volume_slide_saturates() {
+    assert_eq!(neotracker::apply_volume_slide(10, 5), 15);
+    assert_eq!(neotracker::apply_volume_slide(10, -5), 5);
+    assert_eq!(neotracker::apply_volume_slide(60, 3), 63);
+    assert_eq!(neotracker::apply_volume_slide(62, 15), 63);
+    assert_eq!(neotracker::apply_volume_slide(3, -3), 0);
+    assert_eq!(neotracker::apply_volume_slide(1, -15), 0);
+}
+
+#[test]
+fn tracker_string() {
+    use neotracker::Effect;
+    assert_eq!(
+        &Effect::VolumeSlide { up: 0, down: 8 }.to_tracker_string(),
+        b"A08"
+    );
+    assert_eq!(
+        &Effect::VolumeSlide { up: 3, down: 0 }.to_tracker_string(),
+        b"A30"
+    );
+    assert_eq!(&Effect::SetVolume(0x40).to_tracker_string(), b"C40");
+    assert_eq!(&Effect::SetSpeed(0x7D).to_tracker_string(), b"F7D");
+    assert_eq!(&Effect::Arpeggio(0x37).to_tracker_string(), b"037");
+}
+
+#[test]
+fn round_trip() {
+    for value in 0x0001..=0x0FFF {
+        if let Some(effect) = neotracker::Effect::try_from(value) {
+            let back = neotracker::Effect::try_from(effect.to_u16());
+            assert_eq!(back, Some(effect), "{value:03x}");
+        }
+    }
+}
+
+#[test]
+fn volume_slide() {
+    use neotracker::Effect;
+    assert_eq!(
+        Effect::try_from(0x0A30),
+        Some(Effect::VolumeSlide { up: 3, down: 0 })
+    );
+    assert_eq!(
+        Effect::try_from(0x0A05),
+        Some(Effect::VolumeSlide { up: 0, down: 5 })
+    );
+    // Both nibbles are kept, for the caller to pick between
+    assert_eq!(
+        Effect::try_from(0x0A12),
+        Some(Effect::VolumeSlide { up: 1, down: 2 })
+    );
+    assert_eq!(Effect::VolumeSlide { up: 1, down: 2 }.to_u16(), 0x0A12);
+}
+
+#[test]
+fn set_panning() {
+    use neotracker::Effect;
+    assert_eq!(Effect::try_from(0x0840), Some(Effect::SetPanning(0x40)));
+    assert_eq!(Effect::SetPanning(0x40).to_u16(), 0x0840);
+    assert_eq!(Effect::try_from(0x08FF), Some(Effect::SetPanning(0xFF)));
+}
+
+#[test]
+fn tremolo() {
+    use neotracker::Effect;
+    assert_eq!(Effect::try_from(0x0734), Some(Effect::Tremolo(0x34)));
+    assert_eq!(Effect::Tremolo(0x34).to_u16(), 0x0734);
+    assert_eq!(Effect::Tremolo(0x34).speed_and_depth(), Some((3, 4)));
+    assert_eq!(Effect::Vibrato(0xA2).speed_and_depth(), Some((10, 2)));
+    assert_eq!(Effect::SetVolume(0x34).speed_and_depth(), None);
+}
+
+#[test]
+fn extended_effects() {
+    use neotracker::{Effect, ExtendedEffect};
+    assert_eq!(
+        Effect::try_from(0x0E63),
+        Some(Effect::Extended(ExtendedEffect::PatternLoop(3)))
+    );
+    assert_eq!(
+        Effect::try_from(0x0E60),
+        Some(Effect::Extended(ExtendedEffect::PatternLoop(0)))
+    );
+    assert_eq!(
+        Effect::try_from(0x0ED2),
+        Some(Effect::Extended(ExtendedEffect::NoteDelay(2)))
+    );
+    assert_eq!(
+        Effect::try_from(0x0E00),
+        Some(Effect::Extended(ExtendedEffect::SetFilter(0)))
+    );
+    assert_eq!(
+        &Effect::Extended(ExtendedEffect::NoteDelay(2)).to_tracker_string(),
+        b"ED2"
+    );
+    for arg in 0..=0xFF {
+        assert_eq!(ExtendedEffect::from_arg(arg).to_arg(), arg);
+    }
+}
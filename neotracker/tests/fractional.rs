@@ -0,0 +1,74 @@
+//! Tests for the fixed-point `Fractional` type.
+
+use neotracker::Fractional;
+
+#[test]
+fn apply_period_checked() {
+    let rate = Fractional::new_from_sample_rate(8000);
+    assert_eq!(rate.apply_period_checked(0), None);
+    assert_eq!(rate.apply_period_checked(428), Some(rate.apply_period(428)));
+}
+
+#[test]
+fn advance_by() {
+    let step = Fractional::new_from_sample_rate(44100).apply_period(428);
+    let mut repeated = Fractional::new(10);
+    for _ in 0..1000 {
+        repeated += step;
+    }
+    let mut batched = Fractional::new(10);
+    batched.advance_by(step, 1000);
+    assert_eq!(batched, repeated);
+    batched.advance_by(step, 0);
+    assert_eq!(batched, repeated);
+}
+
+#[test]
+fn wrap_loop() {
+    // Step just over one byte per sample
+    let step = Fractional::new_from_sample_rate(8000).apply_period(428);
+    assert_ne!(step.fraction(), 0);
+    let mut position = Fractional::new(0);
+    while position.as_index() < 110 {
+        position += step;
+    }
+    // Loop is bytes 100 to 110
+    let wrapped = position.wrap_loop(100, 10);
+    assert_eq!(wrapped.as_index(), position.as_index() - 10);
+    assert_eq!(wrapped.fraction(), position.fraction());
+    // Inside the loop, nothing happens
+    let inside = Fractional::new(105);
+    assert_eq!(inside.wrap_loop(100, 10), inside);
+    // A long way past the end comes back round
+    assert_eq!(
+        Fractional::new(135).wrap_loop(100, 10),
+        Fractional::new(105)
+    );
+}
+
+#[test]
+fn sub() {
+    assert_eq!(Fractional::new(10) - Fractional::new(3), Fractional::new(7));
+    assert_eq!(
+        Fractional::from_parts(1, 0) - Fractional::from_parts(0, 64),
+        Fractional::from_parts(0, 192)
+    );
+    // Saturates rather than going negative
+    assert_eq!(Fractional::new(3) - Fractional::new(10), Fractional::new(0));
+    let mut position = Fractional::new(5);
+    position -= Fractional::new(2);
+    assert_eq!(position, Fractional::new(3));
+    position -= Fractional::new(4);
+    assert_eq!(position, Fractional::new(0));
+}
+
+#[test]
+fn mul_u32() {
+    let half = Fractional::from_parts(0, 128);
+    assert_eq!(half.mul_u32(3), Fractional::from_parts(1, 128));
+    assert_eq!(Fractional::new(7).mul_u32(0), Fractional::new(0));
+    assert_eq!(
+        Fractional::new(1 << 23).mul_u32(4).as_index(),
+        (u32::MAX >> 8) as usize
+    );
+}
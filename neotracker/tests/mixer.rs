@@ -0,0 +1,101 @@
+//! Tests for the mixer, using small modules built in memory.
+
+mod common;
+
+use common::TestModule;
+use neotracker::{Mixer, MixerChannel};
+
+#[test]
+fn first_frame() {
+    let data = TestModule::new()
+        .set_sample(1, &[64, 64, 64, 64], 64)
+        .set_sample(2, &[(-32i8) as u8; 4], 32)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let first = pt.sample(1).unwrap();
+    let second = pt.sample(2).unwrap();
+    let mut channels = [
+        MixerChannel {
+            data: first.raw_sample_bytes(),
+            period: 428,
+            volume: first.volume(),
+            ..Default::default()
+        },
+        MixerChannel {
+            data: second.raw_sample_bytes(),
+            period: 428,
+            volume: second.volume(),
+            ..Default::default()
+        },
+    ];
+    let mut mixer = Mixer::new(8000);
+    let mut buffer = [0i16; 8];
+    mixer.fill(&mut channels, &mut buffer);
+    // Channel 0 goes left and channel 1 goes right. Each is scaled by 256
+    // and its volume, then divided by 64 and by 2 for headroom.
+    assert_eq!(&buffer[0..2], &[8192, -2048]);
+    // Each channel moves on by a little over one byte per frame
+    assert!(channels[0].position.as_index() >= 4);
+    // And then the one-shot samples stop
+    assert_eq!(channels[0].period, 0);
+    assert_eq!(mixer.next_frame(&mut channels), (0, 0));
+}
+
+#[test]
+fn looping() {
+    let pcm = [10u8, 20, 30, 40];
+    let mut channels = [
+        MixerChannel::default(),
+        MixerChannel::default(),
+        MixerChannel::default(),
+        MixerChannel {
+            data: &pcm,
+            repeat: Some((2, 2)),
+            period: 428,
+            volume: 64,
+            ..Default::default()
+        },
+    ];
+    let mut mixer = Mixer::new(8000);
+    let mut buffer = [0i16; 200];
+    mixer.fill(&mut channels, &mut buffer);
+    // The fourth channel goes left, and keeps playing the loop
+    assert_eq!(channels[3].period, 428);
+    assert!((2..4).contains(&channels[3].position.as_index()));
+    assert!(buffer.chunks_exact(2).all(|frame| frame[1] == 0));
+    let last_left = buffer[buffer.len() - 2];
+    assert!(last_left == 30 * 128 || last_left == 40 * 128);
+}
+
+#[test]
+fn matches_renderer() {
+    let pcm: Vec<u8> = (0..64).map(|x| (x * 4) as u8).collect();
+    let data = TestModule::new()
+        .set_sample(1, &pcm, 50)
+        .set_sample_loop(1, 0, 32)
+        .set_note(0, 0, 0, 1, 113, 0)
+        .set_note(0, 0, 1, 1, 428, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt.clone(), 8000);
+    renderer.set_headroom(3);
+    renderer.set_dither(true);
+    renderer.set_antialias(true);
+
+    let sample = pt.sample(1).unwrap();
+    let voice = |period| MixerChannel {
+        data: sample.raw_sample_bytes(),
+        repeat: Some((0, 64)),
+        period,
+        volume: 50,
+        ..Default::default()
+    };
+    let mut channels = [voice(113), voice(428)];
+    let mut mixer = Mixer::new(8000);
+    mixer.set_headroom(3);
+    mixer.set_dither(true);
+    mixer.set_antialias(true);
+    for _ in 0..500 {
+        assert_eq!(renderer.next_sample(), mixer.next_frame(&mut channels));
+    }
+}
@@ -0,0 +1,817 @@
+//! Tests for whole-module queries, using small modules built in memory.
+
+mod common;
+
+#[test]
+fn duplicate_samples() {
+    let data = common::TestModule::new()
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .set_sample(2, &[5, 6, 7, 8], 64)
+        .set_sample(3, &[1, 2, 3, 4], 32)
+        .set_sample(5, &[5, 6, 7, 8], 64)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.duplicate_samples(), vec![(1, 3), (2, 5)]);
+}
+
+#[test]
+fn noise_tracker_dialect() {
+    let mut data = common::TestModule::new()
+        .set_note(0, 0, 0, 0, 0, 0x0C20)
+        .build();
+    // Noise Tracker stores a restart position after the song length
+    data[951] = 0;
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.likely_dialect(), neotracker::Dialect::NoiseTracker);
+
+    // But a Pro Tracker effect trumps that
+    let mut data = common::TestModule::new()
+        .set_note(0, 0, 0, 0, 0, 0x0ED1)
+        .build();
+    data[951] = 0;
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.likely_dialect(), neotracker::Dialect::ProTracker);
+}
+
+#[test]
+fn comment() {
+    let data = common::TestModule::new()
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(pt.trailing_data().is_empty());
+    assert_eq!(pt.comment(), None);
+
+    let mut data = common::TestModule::new()
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .build();
+    data.extend_from_slice(b"Made by me\r\n\0\0");
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.trailing_data(), b"Made by me\r\n\0\0");
+    assert_eq!(pt.comment(), Some(&b"Made by me\r\n"[..]));
+
+    let mut data = common::TestModule::new().build();
+    data.extend_from_slice(&[0xFF, 0x00, 0x12]);
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.trailing_data(), &[0xFF, 0x00, 0x12]);
+    assert_eq!(pt.comment(), None);
+}
+
+#[test]
+fn monophonic_events() {
+    let data = common::TestModule::new()
+        .set_sample(1, &[0; 4], 32)
+        .set_sample(2, &[0; 4], 64)
+        .set_note(0, 0, 0, 0, 0, 0x0F03)
+        .set_note(0, 1, 0, 1, 428, 0)
+        .set_note(0, 1, 1, 2, 214, 0)
+        // a Set Volume effect trumps the sample's own volume
+        .set_note(0, 2, 2, 1, 214, 0)
+        .set_note(0, 2, 3, 1, 856, 0x0C40)
+        .set_note(0, 3, 2, 2, 214, 0)
+        .set_note(0, 3, 3, 1, 856, 0x0C40)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let events: Vec<(u8, Option<f32>)> = pt.monophonic_events().collect();
+    assert_eq!(events.len(), 64);
+    assert_eq!(events[0], (3, None));
+    assert_eq!(events[1].0, 3);
+    assert!((events[1].1.unwrap() - 523.26).abs() < 0.01);
+    assert!((events[2].1.unwrap() - 130.815).abs() < 0.01);
+    // equally loud - the lower channel wins
+    assert!((events[3].1.unwrap() - 523.26).abs() < 0.01);
+    assert_eq!(events[63], (3, None));
+}
+
+#[test]
+fn suggested_gain() {
+    let quiet = [10, 246, 10, 246];
+    let loud = [127, 129, 127, 129];
+    let mut spike = [0; 64];
+    spike[10] = 100;
+    let data = common::TestModule::new()
+        .set_sample(1, &quiet, 64)
+        .set_sample(2, &loud, 64)
+        // one big spike in silence - can't be turned up much
+        .set_sample(3, &spike, 64)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!((pt.sample(1).unwrap().suggested_gain() - 3.2).abs() < 0.001);
+    assert!((pt.sample(2).unwrap().suggested_gain() - 0.252).abs() < 0.001);
+    assert!((pt.sample(3).unwrap().suggested_gain() - 1.28).abs() < 0.001);
+    // empty
+    assert_eq!(pt.sample(4).unwrap().suggested_gain(), 1.0);
+}
+
+#[test]
+fn natural_sample_rate() {
+    let data = common::TestModule::new()
+        .set_sample(1, &[0; 4], 64)
+        .set_sample(2, &[0; 4], 64)
+        .set_sample_finetune(2, 1)
+        .set_sample(3, &[0; 4], 64)
+        .set_sample_finetune(3, 0x0F)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.sample(1).unwrap().natural_sample_rate(), 8363);
+    // Finetune +1 is higher, and -1 is lower
+    assert_eq!(pt.sample(2).unwrap().natural_sample_rate(), 8422);
+    assert_eq!(pt.sample(3).unwrap().natural_sample_rate(), 8305);
+}
+
+#[test]
+fn root_note() {
+    let data = common::TestModule::new()
+        .set_sample(1, &[0; 4], 64)
+        .set_sample(2, &[0; 4], 64)
+        .set_sample_finetune(2, 3)
+        .set_sample(3, &[0; 4], 64)
+        .set_sample_finetune(3, 4)
+        .set_sample(4, &[0; 4], 64)
+        .set_sample_finetune(4, 0x0C)
+        .set_sample(5, &[0; 4], 64)
+        .set_sample_finetune(5, 0x0D)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.sample(1).unwrap().root_note(), "C-2");
+    assert_eq!(pt.sample(2).unwrap().root_note(), "C-2");
+    // Played sharp, so the root is lower
+    assert_eq!(pt.sample(3).unwrap().root_note(), "B-1");
+    // -4, played flat
+    assert_eq!(pt.sample(4).unwrap().root_note(), "C2♯");
+    assert_eq!(pt.sample(5).unwrap().root_note(), "C-2");
+}
+
+#[test]
+fn pattern_out_of_range() {
+    let data = common::TestModule::new().set_positions(&[0, 1]).build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(pt.pattern(1).is_some());
+    assert!(pt.pattern(2).is_none());
+    assert!(pt.pattern(255).is_none());
+
+    // The order list says there are two patterns, but the file is cut short
+    // after the first one
+    let pt = neotracker::ProTrackerModule::new(&data[0..1084 + 1024]).unwrap();
+    assert!(pt.pattern(0).is_some());
+    assert!(pt.pattern(1).is_none());
+}
+
+#[test]
+fn is_short() {
+    let data = common::TestModule::new().set_positions(&[0, 0]).build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(pt.is_short());
+
+    // Two positions, but very slow
+    let data = common::TestModule::new()
+        .set_positions(&[0, 0])
+        .set_note(0, 0, 0, 0, 0, 0x0F1F)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(!pt.is_short());
+
+    // Twice as many ticks per line, but each tick is half as long
+    let data = common::TestModule::new()
+        .set_positions(&[0, 0])
+        .set_note(0, 0, 0, 0, 0, 0x0F0C)
+        .set_note(0, 0, 1, 0, 0, 0x0FFA)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(pt.is_short());
+
+    let data = common::TestModule::new().set_positions(&[0, 0, 0]).build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(!pt.is_short());
+}
+
+#[test]
+fn max_simultaneous_notes() {
+    let data = common::TestModule::new()
+        .set_positions(&[0, 1])
+        .set_note(0, 3, 0, 1, 428, 0)
+        .set_note(0, 3, 2, 1, 428, 0)
+        .set_note(0, 4, 1, 1, 428, 0)
+        // an effect alone doesn't start a note
+        .set_note(0, 3, 3, 0, 0, 0x0C20)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.max_simultaneous_notes(), 2);
+    let data = common::TestModule::new().build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.max_simultaneous_notes(), 0);
+}
+
+#[test]
+fn sample_debug() {
+    let data = common::TestModule::new()
+        .set_sample(1, &[0; 4], 64)
+        .set_sample_name(1, b"bass")
+        .set_sample_finetune(1, 15)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let sample = pt.sample(1).unwrap();
+    assert_eq!(sample.finetune(), -1);
+    assert_eq!(
+        format!("{sample:?}"),
+        "Sample { info: SampleInfo { sample_no: 1, name: \"bass\", sample_length_bytes: 4, \
+         finetune: -1, finetune_raw: 15, volume: 64, repeat_point: 0, repeat_length: 2 }, \
+         file_offset: 2108 }"
+    );
+    assert_eq!(
+        format!("{:?}", pt.sample_info(1).unwrap()),
+        "SampleInfo { sample_no: 1, name: \"bass\", sample_length_bytes: 4, \
+         finetune: -1, finetune_raw: 15, volume: 64, repeat_point: 0, repeat_length: 2 }"
+    );
+}
+
+#[test]
+fn note_events() {
+    use neotracker::NoteEvent;
+    let data = common::TestModule::new()
+        .set_note(0, 0, 0, 1, 428, 0x0F03)
+        .set_note(0, 4, 0, 1, 214, 0)
+        .set_note(0, 8, 0, 0, 0, 0x0C00)
+        .set_note(0, 2, 1, 1, 856, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let events: Vec<NoteEvent> = pt.note_events().collect();
+    assert_eq!(
+        events,
+        [
+            NoteEvent {
+                channel: 0,
+                start_tick: 0,
+                duration_ticks: 12,
+                period: 428
+            },
+            NoteEvent {
+                channel: 0,
+                start_tick: 12,
+                duration_ticks: 12,
+                period: 214
+            },
+            // Plays until the end of the song
+            NoteEvent {
+                channel: 1,
+                start_tick: 6,
+                duration_ticks: 62 * 3,
+                period: 856
+            },
+        ]
+    );
+}
+
+#[test]
+fn probe_channel_counts() {
+    let mut data = common::TestModule::new().build();
+    for (magic, format) in [
+        (b"M.K.", Some(neotracker::Format::ProTracker4)),
+        (b"M!K!", Some(neotracker::Format::ProTracker4)),
+        (b"FLT4", Some(neotracker::Format::ProTracker4)),
+        (b"6CHN", Some(neotracker::Format::MultiChannel(6))),
+        (b"8CHN", Some(neotracker::Format::MultiChannel(8))),
+        (b"FLT8", Some(neotracker::Format::MultiChannel(8))),
+        (b"16CH", Some(neotracker::Format::MultiChannel(16))),
+        (b"32CN", Some(neotracker::Format::MultiChannel(32))),
+        (b"33CH", None),
+        (b"0CHN", None),
+        (b"ABCD", None),
+    ] {
+        data[1080..1084].copy_from_slice(magic);
+        assert_eq!(neotracker::probe(&data), format, "{:?}", magic);
+    }
+}
+
+#[test]
+fn more_than_64_patterns() {
+    // Pro Tracker tags these M!K!, but the layout is the same as M.K.
+    let order: Vec<u8> = (0..65).collect();
+    let mut data = common::TestModule::new()
+        .set_positions(&order)
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .set_note(64, 0, 0, 1, 428, 0)
+        .build();
+    data[1080..1084].copy_from_slice(b"M!K!");
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.format(), neotracker::Format::ProTracker4);
+    assert_eq!(pt.num_patterns(), 65);
+    assert_eq!(pt.validate(), Ok(()));
+    let note = pt.pattern(64).unwrap().line(0).unwrap().channels()[0];
+    assert_eq!((note.sample_no(), note.period()), (1, 428));
+    assert_eq!(pt.sample(1).unwrap().raw_sample_bytes(), &[1, 2, 3, 4]);
+}
+
+#[test]
+fn six_channels() {
+    // Header, then one pattern of 64 lines with six notes each
+    let mut data = vec![0u8; 1084 + (64 * 6 * 4)];
+    data[950] = 1;
+    data[1080..1084].copy_from_slice(b"6CHN");
+    // Line 1, channel 5: sample 1, period 428
+    let offset = 1084 + (6 * 4) + (5 * 4);
+    data[offset..offset + 4].copy_from_slice(&[0x01, 0xAC, 0x10, 0x00]);
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.channel_count(), 6);
+    assert_eq!(pt.num_patterns(), 1);
+    let pattern = pt.pattern(0).unwrap();
+    let line = pattern.line(1).unwrap();
+    assert_eq!(line.channels().len(), 6);
+    assert_eq!(line.channels()[5].period(), 428);
+    assert_eq!(line.channels()[5].sample_no(), 1);
+    assert!(line.channels()[0..5].iter().all(|n| n.is_empty()));
+    assert_eq!(line.channel(5), Some(&line.channels()[5]));
+    assert_eq!(line.channel(6), None);
+    assert_eq!(pattern.lines().count(), 64);
+    // Without the last four bytes, the pattern is incomplete
+    assert_eq!(
+        neotracker::ProTrackerModule::new(&data[0..data.len() - 4]).err(),
+        Some(neotracker::Error::FileTooSmall)
+    );
+}
+
+#[test]
+fn loop_seam_discontinuity() {
+    // A ramp from -100 up to +100, looped in full, jumps right back down
+    let ramp: Vec<u8> = (0..=200).map(|n| (n as i16 - 100) as i8 as u8).collect();
+    let data = common::TestModule::new()
+        .set_sample(1, &ramp[0..200], 64)
+        .set_sample_loop(1, 0, 100)
+        .set_sample(2, &[50; 400], 64)
+        .set_sample_loop(2, 0, 200)
+        .set_sample(3, &ramp[0..200], 64)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.sample(1).unwrap().loop_seam_discontinuity(), Some(199));
+    assert_eq!(pt.sample(2).unwrap().loop_seam_discontinuity(), Some(0));
+    assert_eq!(pt.sample(3).unwrap().loop_seam_discontinuity(), None);
+}
+
+#[test]
+fn zero_repeat_length() {
+    let data = common::TestModule::new()
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .set_sample_loop(1, 1, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let sample = pt.sample(1).unwrap();
+    assert!(!sample.loops());
+    // Plays through once, rather than sticking on the repeat point
+    let bytes: Vec<u8> = sample.sample_bytes_iter().take(5).collect();
+    assert_eq!(bytes, [1, 2, 3, 4]);
+    assert_eq!(sample.loop_iter().next(), None);
+}
+
+#[test]
+fn sample_bytes_iter_interpolated() {
+    let data = common::TestModule::new()
+        .set_sample(1, &[0, 10, 20, 30], 64)
+        .set_sample(2, &[0, 10, 20, 30], 64)
+        .set_sample_loop(2, 0, 2)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let half = neotracker::Fractional::from_parts(0, 128);
+
+    let one_shot = pt.sample(1).unwrap();
+    let bytes: Vec<u8> = one_shot.sample_bytes_iter_interpolated(half).collect();
+    assert_eq!(bytes, [0, 5, 10, 15, 20, 25, 30, 30]);
+
+    // Blends from the end of the loop back to the start
+    let looped = pt.sample(2).unwrap();
+    let bytes: Vec<u8> = looped
+        .sample_bytes_iter_interpolated(half)
+        .take(10)
+        .collect();
+    assert_eq!(bytes, [0, 5, 10, 15, 20, 25, 30, 15, 0, 5]);
+
+    // Negative values are blended as signed numbers
+    let data = common::TestModule::new()
+        .set_sample(1, &[0xF6, 10], 64)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let sample = pt.sample(1).unwrap();
+    let bytes: Vec<u8> = sample.sample_bytes_iter_interpolated(half).collect();
+    assert_eq!(bytes, [0xF6, 0, 10, 10]);
+}
+
+#[test]
+fn stored_pattern_count() {
+    // Pattern 2 is in the order table, but past the end of the song
+    let mut data = common::TestModule::new()
+        .set_positions(&[0, 1, 2])
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .build();
+    data[950] = 2;
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.song_positions(), &[0, 1]);
+    assert_eq!(pt.stored_pattern_count(), 3);
+    assert_eq!(pt.num_patterns(), 3);
+    // The sample data comes after all three patterns
+    assert_eq!(pt.sample(1).unwrap().raw_sample_bytes(), &[1, 2, 3, 4]);
+    assert!(pt.pattern(2).is_some());
+}
+
+#[test]
+fn order_names_impossible_pattern() {
+    // Past the end of the song, but it still counts towards the patterns
+    // stored, so it is rejected rather than read as 256 patterns
+    let mut data = common::TestModule::new().build();
+    data[953] = 255;
+    assert_eq!(
+        neotracker::ProTrackerModule::new_verbose(&data).err(),
+        Some(neotracker::DetailedError::InvalidOrder {
+            position: 1,
+            pattern: 255
+        })
+    );
+    assert_eq!(
+        neotracker::ProTrackerModule::new(&data).err(),
+        Some(neotracker::Error::InvalidOrder)
+    );
+    // 127 is allowed, although this file is too short to hold it
+    data[953] = 127;
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.stored_pattern_count(), 128);
+    assert!(pt.pattern(127).is_none());
+}
+
+#[test]
+fn inconsistent_layout() {
+    let data = common::TestModule::new()
+        .set_positions(&[0, 1])
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.validate(), Ok(()));
+
+    // Cut the file off part way through the second pattern
+    let short = &data[0..1084 + 1024 + 512];
+    let pt = neotracker::ProTrackerModule::new(short).unwrap();
+    assert_eq!(pt.validate(), Err(neotracker::Error::InconsistentLayout));
+    assert!(pt.pattern(0).is_some());
+    assert!(pt.pattern(1).is_none());
+    assert_eq!(pt.song_patterns().count(), 1);
+    assert_eq!(pt.sample(1).unwrap().raw_sample_bytes(), &[] as &[u8]);
+    assert!(pt.trailing_data().is_empty());
+
+    // A loop which runs past the end of the sample is allowed
+    let data = common::TestModule::new()
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .set_sample_loop(1, 1, 2)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.validate(), Ok(()));
+}
+
+#[test]
+fn truncated_sample_data() {
+    let data = common::TestModule::new()
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .set_sample(2, &[5, 6, 7, 8], 64)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.validate(), Ok(()));
+
+    // Cut the file off part way through the second sample
+    let short = &data[0..data.len() - 1];
+    let pt = neotracker::ProTrackerModule::new(short).unwrap();
+    assert_eq!(pt.validate(), Err(neotracker::Error::TruncatedSampleData));
+    assert_eq!(pt.sample(1).unwrap().raw_sample_bytes(), &[1, 2, 3, 4]);
+    assert_eq!(pt.sample(2).unwrap().raw_sample_bytes(), &[5, 6, 7]);
+}
+
+#[test]
+fn sample_i8_iter() {
+    let data = common::TestModule::new()
+        .set_sample(1, &[0xFF, 0x80, 0x7F, 0x00], 64)
+        .set_sample_loop(1, 0, 2)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let sample = pt.sample(1).unwrap();
+    let values: Vec<i8> = sample.sample_i8_iter().take(6).collect();
+    assert_eq!(values, [-1, -128, 127, 0, -1, -128]);
+}
+
+#[test]
+fn empty_pattern() {
+    let data = common::TestModule::new()
+        .set_positions(&[0, 1])
+        .set_note(1, 63, 3, 0, 0, 0x0F03)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(pt.pattern(0).unwrap().is_empty());
+    assert!(!pt.pattern(1).unwrap().is_empty());
+}
+
+/// A 15 sample Soundtracker module, with one pattern and one sample
+fn soundtracker_module() -> Vec<u8> {
+    let mut data = vec![0u8; 600 + 1024];
+    data[0..5].copy_from_slice(b"st-01");
+    // Sample 1: four bytes long, full volume, no loop
+    data[20..24].copy_from_slice(b"bass");
+    data[42..44].copy_from_slice(&2u16.to_be_bytes());
+    data[45] = 64;
+    data[48..50].copy_from_slice(&1u16.to_be_bytes());
+    // Song length, then the tempo byte Soundtracker puts here
+    data[470] = 1;
+    data[471] = 120;
+    // Line 0, channel 2: sample 1, period 428
+    data[600 + 8..600 + 12].copy_from_slice(&[0x01, 0xAC, 0x10, 0x00]);
+    data.extend_from_slice(&[10, 20, 30, 40]);
+    data
+}
+
+#[test]
+fn soundtracker() {
+    let data = soundtracker_module();
+    assert_eq!(
+        neotracker::probe(&data),
+        Some(neotracker::Format::SoundTracker15)
+    );
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.song_name(), b"st-01");
+    assert_eq!(pt.sample_count(), 15);
+    assert_eq!(pt.samples().len(), 15);
+    assert!(pt.sample_info(16).is_none());
+    assert_eq!(pt.song_positions(), &[0]);
+    assert_eq!(pt.restart_position(), 120);
+    let sample = pt.sample(1).unwrap();
+    assert_eq!(sample.name(), b"bass");
+    assert_eq!(sample.raw_sample_bytes(), &[10, 20, 30, 40]);
+    let line = pt.pattern(0).unwrap().line(0).unwrap();
+    assert_eq!(line.channels()[2].sample_no(), 1);
+    assert_eq!(line.channels()[2].period(), 428);
+    assert_eq!(pt.validate(), Ok(()));
+    assert_eq!(pt.layout().patterns, 600..1624);
+    assert_eq!(pt.layout().magic, 600..600);
+    assert_eq!(pt.layout().samples[0], 1624..1628);
+    assert_eq!(pt.layout().samples[15], 0..0);
+    assert!(pt.trailing_data().is_empty());
+}
+
+#[test]
+fn soundtracker_extract_pattern() {
+    let data = soundtracker_module();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let owned = pt.extract_pattern_module(0).unwrap();
+    let extracted = owned.module();
+    assert_eq!(extracted.format(), neotracker::Format::ProTracker4);
+    assert_eq!(extracted.sample_count(), 31);
+    assert_eq!(
+        extracted.sample(1).unwrap().raw_sample_bytes(),
+        &[10, 20, 30, 40]
+    );
+    assert_eq!(
+        extracted.pattern(0).unwrap().to_grid::<4>()[0][2].period(),
+        428
+    );
+}
+
+#[test]
+fn soundtracker_rebuild() {
+    let data = soundtracker_module();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let owned = pt.rebuild().unwrap();
+    let rebuilt = owned.module();
+    assert_eq!(rebuilt.format(), neotracker::Format::ProTracker4);
+    assert_eq!(rebuilt.song_name(), b"st-01");
+    assert_eq!(rebuilt.song_positions(), &[0]);
+    // Pro Tracker's value replaces the tempo
+    assert_eq!(rebuilt.restart_position(), 127);
+    let sample = rebuilt.sample(1).unwrap();
+    assert_eq!(sample.name(), b"bass");
+    assert_eq!(sample.raw_sample_bytes(), &[10, 20, 30, 40]);
+    assert_eq!(rebuilt.sample(16).unwrap().sample_length(), 0);
+    assert_eq!(
+        rebuilt.pattern(0).unwrap().to_array(),
+        pt.pattern(0).unwrap().to_array()
+    );
+}
+
+#[test]
+fn not_soundtracker() {
+    // Each of these breaks the header in a way a real file would not
+    let corruptions: [fn(&mut Vec<u8>); 5] = [
+        |data| data[470] = 0,
+        |data| data[472] = 64,
+        |data| data[45] = 65,
+        |data| data[0] = 0xFF,
+        |data| data.truncate(1600),
+    ];
+    for corrupt in corruptions {
+        let mut data = soundtracker_module();
+        corrupt(&mut data);
+        assert_eq!(neotracker::probe(&data), None);
+        assert!(neotracker::ProTrackerModule::new(&data).is_err());
+    }
+}
+
+#[test]
+fn length_looks_truncated() {
+    let mut data = common::TestModule::new()
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(!pt.sample(1).unwrap().length_looks_truncated());
+    assert!(!pt.sample(2).unwrap().length_looks_truncated());
+
+    // Sample 1 was really 128 KiB longer, and its length wrapped around
+    data.resize(data.len() + 0x20000, 0);
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(pt.sample(1).unwrap().length_looks_truncated());
+    assert!(!pt.sample(2).unwrap().length_looks_truncated());
+}
+
+#[test]
+fn duration_secs() {
+    // An empty pattern at the default speed lasts 64 lines of 6 ticks
+    let data = common::TestModule::new().build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!((pt.duration_secs(6, 125) - 64.0 * 6.0 * 0.02).abs() < 0.001);
+
+    // Speed 3, and then 250 BPM from line 10. The second pattern breaks
+    // straight away to line 10 of the third position.
+    let data = common::TestModule::new()
+        .set_positions(&[0, 1, 0])
+        .set_note(0, 0, 0, 0, 0, 0xF03)
+        .set_note(0, 10, 1, 0, 0, 0xFFA)
+        .set_note(1, 0, 2, 0, 0, 0xD10)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let expected = 10.0 * 0.06 + 54.0 * 0.03 + 0.03 + 54.0 * 0.03;
+    assert!((pt.duration_secs(6, 125) - expected).abs() < 0.001);
+
+    // A jump back to the start is only played once
+    let data = common::TestModule::new()
+        .set_positions(&[0, 1])
+        .set_note(1, 3, 0, 0, 0, 0xB00)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!((pt.duration_secs(6, 125) - 68.0 * 0.12).abs() < 0.001);
+}
+
+#[test]
+fn song_length_too_long() {
+    // The order table only has 128 entries
+    let mut data = common::TestModule::new().build();
+    data[950] = 200;
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.song_length(), 128);
+    assert_eq!(pt.song_positions().len(), 128);
+    assert_eq!(pt.song_position(127), Some(0));
+    assert_eq!(pt.song_position(128), None);
+    assert_eq!(pt.song_patterns().count(), 128);
+    // Every position plays pattern 0, which is empty
+    assert!((pt.duration_secs(6, 125) - 128.0 * 64.0 * 0.12).abs() < 0.1);
+}
+
+#[test]
+fn module_builder() {
+    use neotracker::{Effect, ModuleBuilder, Note};
+    let pcm = [0u8, 16, 32, 48, 64, 80, 96, 112, 127];
+    let mut builder = ModuleBuilder::new();
+    builder.set_title(b"built from code");
+    assert_eq!(builder.add_sample(b"ramp", &pcm, 48, -2, 1, 3), Ok(1));
+    let mut lines = [[Note::new(0, 0, None); 4]; 64];
+    lines[0][0] = Note::new(1, 428, Some(Effect::SetVolume(32)));
+    lines[63][3] = Note::new(1, 214, Some(Effect::SetSpeed(3)));
+    assert_eq!(builder.add_pattern(&lines), Ok(0));
+    builder.set_positions(&[0, 0]).unwrap();
+    assert!(builder.set_positions(&[]).is_err());
+    let data = builder.build();
+
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(pt.validate().is_ok());
+    assert_eq!(pt.format(), neotracker::Format::ProTracker4);
+    assert_eq!(&pt.song_name()[0..15], b"built from code");
+    assert_eq!(pt.song_positions(), &[0, 0]);
+    assert_eq!(pt.num_patterns(), 1);
+    let sample = pt.sample(1).unwrap();
+    assert_eq!(&sample.name()[0..4], b"ramp");
+    assert_eq!(sample.volume(), 48);
+    assert_eq!(sample.finetune(), -2);
+    assert_eq!(sample.repeat_point(), 1);
+    assert_eq!(sample.repeat_length(), 3);
+    // Padded out to a whole number of words
+    assert_eq!(&sample.raw_sample_bytes()[0..9], &pcm);
+    assert_eq!(sample.sample_length_bytes(), 10);
+    assert!(pt.sample(2).unwrap().raw_sample_bytes().is_empty());
+    let grid = pt.pattern(0).unwrap().to_grid::<4>();
+    assert_eq!(grid[0][0].sample_no(), 1);
+    assert_eq!(grid[0][0].period(), 428);
+    assert_eq!(grid[0][0].effect(), Some(Effect::SetVolume(32)));
+    assert_eq!(grid[63][3].period(), 214);
+    assert_eq!(grid[63][3].effect(), Some(Effect::SetSpeed(3)));
+    assert!(grid[1][1].is_empty());
+
+    // An unplayed pattern is still stored
+    let mut builder = ModuleBuilder::new();
+    builder.add_pattern(&lines).unwrap();
+    builder.add_pattern(&lines).unwrap();
+    let data = builder.build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.song_positions(), &[0]);
+    assert_eq!(pt.num_patterns(), 2);
+    assert_eq!(data.len(), 1084 + 2 * 1024);
+    assert_eq!(&data[1080..1084], b"M.K.");
+
+    // More than 64 patterns changes the tag
+    let mut builder = ModuleBuilder::new();
+    builder.set_positions(&[64]).unwrap();
+    let data = builder.build();
+    assert_eq!(&data[1080..1084], b"M!K!");
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.num_patterns(), 65);
+
+    // The builder says when something will not fit
+    let mut builder = ModuleBuilder::new();
+    for _ in 0..31 {
+        builder.add_sample(b"", &[], 0, 0, 0, 1).unwrap();
+    }
+    assert_eq!(
+        builder.add_sample(b"", &[], 0, 0, 0, 1),
+        Err(neotracker::Error::TooManySamples)
+    );
+    for _ in 0..128 {
+        builder.add_pattern(&lines).unwrap();
+    }
+    assert_eq!(
+        builder.add_pattern(&lines),
+        Err(neotracker::Error::TooManyPatterns)
+    );
+}
+
+#[test]
+fn module_builder_long_sample() {
+    use neotracker::ModuleBuilder;
+    // Lengths of 0x8000 words or more are over 64 KiB
+    let pcm: Vec<u8> = (0..0x1_0002).map(|x| x as u8).collect();
+    let mut builder = ModuleBuilder::new();
+    assert_eq!(builder.add_sample(b"all", &pcm, 64, 0, 0, 0x8001), Ok(1));
+    assert_eq!(builder.add_sample(b"end", &pcm, 64, 0, 0x8000, 1), Ok(2));
+    // 0xFFFF words is the most a sample can hold
+    assert_eq!(
+        builder.add_sample(b"big", &[0; 0x1_FFFF], 64, 0, 0, 1),
+        Err(neotracker::Error::SampleTooLong)
+    );
+    let data = builder.build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert!(pt.validate().is_ok());
+    let sample = pt.sample(1).unwrap();
+    assert_eq!(sample.sample_length(), 0x8001);
+    assert_eq!(sample.sample_length_bytes(), 0x1_0002);
+    assert_eq!(sample.repeat_point_bytes(), 0);
+    assert_eq!(sample.repeat_length_bytes(), 0x1_0002);
+    assert_eq!(sample.raw_sample_bytes(), &pcm[..]);
+    let sample = pt.sample(2).unwrap();
+    assert_eq!(sample.repeat_point_bytes(), 0x1_0000);
+    assert_eq!(sample.repeat_length_bytes(), 2);
+    assert_eq!(sample.raw_sample_bytes(), &pcm[..]);
+}
+
+#[test]
+fn unused_patterns() {
+    let data = common::TestModule::new()
+        .set_positions(&[0, 1, 2, 4, 1])
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.stored_pattern_count(), 5);
+    assert_eq!(pt.unused_patterns().collect::<Vec<u8>>(), vec![3]);
+
+    // Every pattern played
+    let data = common::TestModule::new().set_positions(&[1, 0]).build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    assert_eq!(pt.unused_patterns().count(), 0);
+}
+
+#[test]
+fn new_verbose() {
+    use neotracker::DetailedError;
+    let data = vec![0u8; 1000];
+    assert_eq!(
+        neotracker::ProTrackerModule::new_verbose(&data).err(),
+        Some(DetailedError::TooSmall {
+            have: 1000,
+            need: 2108
+        })
+    );
+
+    let mut data = common::TestModule::new().build();
+    assert!(neotracker::ProTrackerModule::new_verbose(&data).is_ok());
+    // Cut off part way through the first pattern
+    assert_eq!(
+        neotracker::ProTrackerModule::new_verbose(&data[0..2000]).err(),
+        Some(DetailedError::TooSmall {
+            have: 2000,
+            need: 2108
+        })
+    );
+
+    data[1080..1084].copy_from_slice(b"ABCD");
+    let err = neotracker::ProTrackerModule::new_verbose(&data).unwrap_err();
+    assert_eq!(err, DetailedError::WrongMagicValue { found: *b"ABCD" });
+    assert_eq!(
+        neotracker::Error::from(err),
+        neotracker::Error::WrongMagicValue
+    );
+}
@@ -0,0 +1,214 @@
+//! Tests for decoding individual pattern cells, using small modules built in
+//! memory.
+
+mod common;
+
+use common::TestModule;
+
+#[test]
+fn effect_arg_signed() {
+    let data = TestModule::new()
+        .set_note(0, 0, 0, 0, 0, 0x0A30)
+        .set_note(0, 0, 1, 0, 0, 0x0A05)
+        .set_note(0, 0, 2, 0, 0, 0x0A00)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let line = pt.pattern(0).unwrap().line(0).unwrap();
+    assert_eq!(line.channels()[0].effect_arg_signed(), 3);
+    assert_eq!(line.channels()[1].effect_arg_signed(), -5);
+    assert_eq!(line.channels()[2].effect_arg_signed(), 0);
+}
+
+#[test]
+fn note_index() {
+    let data = TestModule::new()
+        .set_note(0, 0, 0, 1, 856, 0)
+        .set_note(0, 0, 1, 1, 428, 0)
+        .set_note(0, 0, 2, 1, 113, 0)
+        .set_note(0, 0, 3, 1, 430, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let line = pt.pattern(0).unwrap().line(0).unwrap();
+    assert_eq!(line.channels()[0].note_index(), Some(0));
+    assert_eq!(line.channels()[1].note_index(), Some(12));
+    assert_eq!(line.channels()[1].musical_note(), Some("C-2"));
+    assert_eq!(line.channels()[2].note_index(), Some(35));
+    // Not an exact match
+    assert_eq!(line.channels()[3].note_index(), None);
+    assert_eq!(line.channels()[3].musical_note(), None);
+}
+
+#[test]
+fn note_names() {
+    let names: Vec<&str> = neotracker::PERIOD_NOTE_MAP
+        .iter()
+        .map(|(_, name)| *name)
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "C-1", "C1♯", "D-1", "D1♯", "E-1", "F-1", "F1♯", "G-1", "G1♯", "A-1", "A1♯", "B-1",
+            "C-2", "C2♯", "D-2", "D2♯", "E-2", "F-2", "F2♯", "G-2", "G2♯", "A-2", "A2♯", "B-2",
+            "C-3", "C3♯", "D-3", "D3♯", "E-3", "F-3", "F3♯", "G-3", "G3♯", "A-3", "A3♯", "B-3",
+        ]
+    );
+    // Highest period (lowest note) first
+    assert!(neotracker::PERIOD_NOTE_MAP
+        .windows(2)
+        .all(|pair| pair[0].0 > pair[1].0));
+}
+
+#[test]
+fn frequency() {
+    let data = TestModule::new()
+        .set_note(0, 0, 0, 1, 428, 0)
+        .set_note(0, 0, 1, 1, 214, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let line = pt.pattern(0).unwrap().line(0).unwrap();
+    assert_eq!(line.channels()[0].frequency(), Some(8363));
+    assert_eq!(line.channels()[1].frequency(), Some(16726));
+    assert_eq!(line.channels()[2].frequency(), None);
+    assert_eq!(neotracker::PAL_CLOCK / 428, 8287);
+}
+
+#[test]
+fn period_for_note() {
+    assert_eq!(neotracker::period_for_note("C-2"), Some(428));
+    assert_eq!(neotracker::period_for_note("A-3"), Some(127));
+    assert_eq!(neotracker::period_for_note("B-3"), Some(113));
+    assert_eq!(neotracker::period_for_note("C1♯"), Some(808));
+    assert_eq!(neotracker::period_for_note("C#1"), None);
+    assert_eq!(neotracker::period_for_note("Z-9"), None);
+}
+
+#[test]
+fn period_from_index() {
+    assert_eq!(neotracker::period_from_index(0), Some(856));
+    assert_eq!(neotracker::period_from_index(12), Some(428));
+    assert_eq!(neotracker::period_from_index(35), Some(113));
+    assert_eq!(neotracker::period_from_index(36), None);
+    // Transposing up an octave is just adding 12
+    let note = neotracker::Note::new(1, 428, None);
+    let index = note.note_index().unwrap();
+    assert_eq!(index, 12);
+    assert_eq!(neotracker::period_from_index(index + 12), Some(214));
+}
+
+#[test]
+fn shift_period() {
+    assert_eq!(neotracker::shift_period(428, 0), Some(428));
+    assert_eq!(neotracker::shift_period(428, 2), Some(381));
+    assert_eq!(neotracker::shift_period(856, 35), Some(113));
+    assert_eq!(neotracker::shift_period(113, 1), None);
+    assert_eq!(neotracker::shift_period(427, 2), None);
+}
+
+#[test]
+fn shift_period_finetuned() {
+    // Slightly off C-2 goes to D-2
+    assert_eq!(neotracker::shift_period_finetuned(427, 2, 0), Some(381));
+    assert_eq!(neotracker::shift_period_finetuned(428, 2, 0), Some(381));
+    // C-2 to D-2 at finetune +3 and -8
+    assert_eq!(neotracker::shift_period_finetuned(419, 2, 3), Some(373));
+    assert_eq!(neotracker::shift_period_finetuned(453, 2, -8), Some(404));
+    // Off the end of the table
+    assert_eq!(neotracker::shift_period_finetuned(113, 1, 0), None);
+    assert_eq!(neotracker::shift_period_finetuned(1000, 0, 0), None);
+}
+
+#[test]
+fn has_effect() {
+    let data = TestModule::new()
+        .set_note(0, 0, 0, 1, 428, 0)
+        .set_note(0, 0, 1, 1, 428, 0x0C20)
+        // Arpeggio with no argument is no effect at all
+        .set_note(0, 0, 2, 0, 0, 0x0000)
+        .set_note(0, 0, 3, 0, 0, 0x0E00)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let line = pt.pattern(0).unwrap().line(0).unwrap();
+    for note in line.channels() {
+        assert_eq!(note.has_effect(), note.effect().is_some());
+    }
+    assert!(!line.channels()[0].has_effect());
+    assert!(line.channels()[1].has_effect());
+    assert!(!line.channels()[2].has_effect());
+    assert!(line.channels()[3].has_effect());
+}
+
+#[test]
+fn effect_command_and_arg() {
+    let data = TestModule::new()
+        .set_note(0, 0, 0, 1, 428, 0x0C20)
+        .set_note(0, 0, 1, 31, 113, 0x0FFF)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let line = pt.pattern(0).unwrap().line(0).unwrap();
+    assert_eq!(line.channels()[0].effect_command(), 12);
+    assert_eq!(line.channels()[0].effect_arg(), 0x20);
+    // The sample number and period bits do not leak in
+    assert_eq!(line.channels()[1].effect_command(), 15);
+    assert_eq!(line.channels()[1].effect_arg(), 0xFF);
+    assert_eq!(line.channels()[2].effect_command(), 0);
+    assert_eq!(line.channels()[2].effect_arg(), 0);
+}
+
+#[test]
+fn same_pitch() {
+    let data = TestModule::new()
+        .set_note(0, 0, 0, 1, 428, 0)
+        .set_note(0, 0, 1, 1, 428, 0x0C20)
+        .set_note(0, 0, 2, 2, 428, 0)
+        .set_note(0, 0, 3, 1, 404, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let line = pt.pattern(0).unwrap().line(0).unwrap();
+    let notes = line.channels();
+    assert!(notes[0].same_pitch(&notes[1]));
+    assert!(!notes[0].same_pitch(&notes[2]));
+    assert!(!notes[0].same_pitch(&notes[3]));
+}
+
+#[test]
+fn equality_and_debug() {
+    let data = TestModule::new()
+        .set_note(0, 0, 0, 5, 428, 0x0C20)
+        .set_note(0, 0, 1, 5, 428, 0x0C20)
+        .set_note(0, 0, 2, 5, 428, 0x0C21)
+        .set_note(0, 0, 3, 17, 427, 0x0000)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let line = pt.pattern(0).unwrap().line(0).unwrap();
+    let notes = line.channels();
+    assert_eq!(notes[0], notes[1]);
+    assert_ne!(notes[0], notes[2]);
+    assert_eq!(format!("{:?}", notes[0]), "C-2 05 C20");
+    assert_eq!(format!("{:?}", notes[3]), "??? 17 000");
+    let empty = pt.pattern(0).unwrap().line(1).unwrap().channels()[0];
+    assert_eq!(format!("{:?}", empty), "--- 00 000");
+    assert_eq!(
+        format!("{:?}", neotracker::Note::new(1, 202, None)),
+        "C3♯ 01 000"
+    );
+}
+
+#[test]
+fn has_pitch_and_triggers_sample() {
+    let data = TestModule::new()
+        .set_note(0, 0, 0, 0, 0, 0x0C20)
+        .set_note(0, 0, 1, 1, 428, 0)
+        .set_note(0, 0, 2, 0, 428, 0)
+        .set_note(0, 0, 3, 1, 0, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let line = pt.pattern(0).unwrap().line(0).unwrap();
+    let notes = line.channels();
+    // Only an effect
+    assert!(!notes[0].has_pitch());
+    assert!(!notes[0].triggers_sample());
+    assert!(!notes[0].is_empty());
+    assert!(notes[1].has_pitch() && notes[1].triggers_sample());
+    assert!(notes[2].has_pitch() && !notes[2].triggers_sample());
+    assert!(!notes[3].has_pitch() && notes[3].triggers_sample());
+}
@@ -0,0 +1,83 @@
+//! Tests for modules which own their data.
+
+mod common;
+
+use std::sync::Arc;
+
+use common::TestModule;
+use neotracker::OwnedModule;
+
+#[test]
+fn rejects_bad_data() {
+    let data = vec![0u8; 100].into_boxed_slice();
+    assert_eq!(
+        OwnedModule::new(data).unwrap_err(),
+        neotracker::Error::FileTooSmall
+    );
+}
+
+#[test]
+fn shared_between_threads() {
+    let data = TestModule::new()
+        .set_title(b"shared")
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .build();
+    let owned = Arc::new(OwnedModule::new(data.into_boxed_slice()).unwrap());
+
+    let other = Arc::clone(&owned);
+    let handle = std::thread::spawn(move || other.module().song_name().to_vec());
+
+    let module = owned.module();
+    assert_eq!(module.sample(1).unwrap().raw_sample_bytes(), &[1, 2, 3, 4]);
+    assert_eq!(handle.join().unwrap(), b"shared");
+}
+
+#[test]
+fn set_order() {
+    let data = TestModule::new()
+        .set_positions(&[0, 1, 2])
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .build();
+    let mut owned = OwnedModule::new(data.into_boxed_slice()).unwrap();
+
+    owned.set_order(&[1, 1, 0, 2, 0]).unwrap();
+    let module = owned.module();
+    assert_eq!(module.song_length(), 5);
+    assert_eq!(module.song_positions(), &[1, 1, 0, 2, 0]);
+
+    // Dropping the last pattern must not lose track of the samples
+    owned.set_order(&[1, 0]).unwrap();
+    let module = owned.module();
+    assert_eq!(module.song_positions(), &[1, 0]);
+    assert_eq!(module.num_patterns(), 3);
+    assert_eq!(module.sample(1).unwrap().raw_sample_bytes(), &[1, 2, 3, 4]);
+
+    assert_eq!(owned.set_order(&[]), Err(neotracker::Error::InvalidOrder));
+    assert_eq!(owned.set_order(&[3]), Err(neotracker::Error::InvalidOrder));
+    assert_eq!(
+        owned.set_order(&[0; 129]),
+        Err(neotracker::Error::InvalidOrder)
+    );
+    assert_eq!(
+        owned.set_order(&[0; 128]),
+        Err(neotracker::Error::InvalidOrder)
+    );
+    // Nothing was changed by the failures
+    assert_eq!(owned.module().song_positions(), &[1, 0]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn from_reader() {
+    let data = TestModule::new().set_title(b"read me").build();
+    let owned = OwnedModule::from_reader(std::io::Cursor::new(&data)).unwrap();
+    assert_eq!(owned.as_bytes(), &data[..]);
+
+    let err = OwnedModule::from_reader(std::io::Cursor::new(&data[0..100])).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    let inner = err.into_inner().unwrap();
+    assert_eq!(
+        inner.downcast_ref::<neotracker::Error>(),
+        Some(&neotracker::Error::FileTooSmall)
+    );
+}
@@ -0,0 +1,597 @@
+//! Tests for the renderer, using small modules built in memory.
+
+mod common;
+
+use common::TestModule;
+
+#[test]
+fn period_without_sample_retriggers() {
+    // A click at the start of the sample, and then silence
+    let mut pcm = vec![0u8; 4096];
+    pcm[0] = 100;
+    let data = TestModule::new()
+        .set_sample(1, &pcm, 64)
+        .set_note(0, 0, 0, 1, 428, 0)
+        // A new pitch, but no sample number
+        .set_note(0, 1, 0, 0, 214, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    // At 8000 Hz, there are 160 samples per tick, and 6 ticks per line
+    let left: Vec<i16> = (0..960 * 2).map(|_| renderer.next_sample().0).collect();
+    assert_eq!(left[0], 100 * 256 / 2);
+    assert_eq!(left[959], 0);
+    // The sample starts again from position 0, at the new period
+    assert_eq!(renderer.line(), 1);
+    assert_eq!(left[960], 100 * 256 / 2);
+    assert_eq!(left[961], 0);
+}
+
+#[test]
+fn speed_zero_is_ignored() {
+    // Speed 3, and then a speed of 0, which should change nothing
+    let data = TestModule::new()
+        .set_note(0, 0, 0, 0, 0, 0x0F03)
+        .set_note(0, 1, 0, 0, 0, 0x0F00)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    for _ in 0..(2 * 480) {
+        renderer.next_sample();
+    }
+    assert_eq!(renderer.line(), 1);
+    renderer.next_sample();
+    assert_eq!(renderer.line(), 2);
+    for _ in 1..480 {
+        renderer.next_sample();
+    }
+    assert_eq!(renderer.line(), 2);
+    renderer.next_sample();
+    assert_eq!(renderer.line(), 3);
+}
+
+#[test]
+fn position_jump_is_followed() {
+    let data = TestModule::new()
+        .set_positions(&[0, 1, 2])
+        .set_note(0, 3, 0, 0, 0, 0x0B02)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    // Play lines 0 to 3 of the first position
+    for _ in 0..(4 * 960) {
+        renderer.next_sample();
+    }
+    assert_eq!((renderer.position(), renderer.line()), (0, 3));
+    renderer.next_sample();
+    assert_eq!((renderer.position(), renderer.line()), (2, 0));
+}
+
+#[test]
+fn backward_jump_ends_song() {
+    let data = TestModule::new()
+        .set_positions(&[0, 1])
+        .set_note(1, 3, 0, 0, 0, 0x0B00)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    // All of the first position, then lines 0 to 3 of the second
+    for _ in 0..((64 + 4) * 960) {
+        renderer.next_sample();
+    }
+    assert_eq!((renderer.position(), renderer.line()), (1, 3));
+    assert!(!renderer.finished());
+    // Going back to position 0 would loop forever, so the song ends
+    assert_eq!(renderer.next_sample(), (0, 0));
+    assert!(renderer.finished());
+    assert_eq!((renderer.position(), renderer.line()), (1, 3));
+}
+
+#[test]
+fn pattern_break_row_is_decimal() {
+    let data = TestModule::new()
+        .set_positions(&[0, 1])
+        .set_note(0, 0, 0, 0, 0, 0x0D10)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    for _ in 0..960 {
+        renderer.next_sample();
+    }
+    assert_eq!((renderer.position(), renderer.line()), (0, 0));
+    // 0x10 means row 10, not row 16
+    renderer.next_sample();
+    assert_eq!((renderer.position(), renderer.line()), (1, 10));
+}
+
+#[test]
+fn tick_effects_do_not_overflow() {
+    let data = TestModule::new()
+        .set_sample(1, &[64u8; 4096], 64)
+        // Slide up by far more than the period
+        .set_note(0, 0, 0, 1, 113, 0x01FF)
+        // An arpeggio whose second note is below its first
+        .set_note(0, 0, 1, 1, 428, 0x00F0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    for _ in 0..=960 {
+        renderer.next_sample();
+    }
+    assert_eq!(renderer.line(), 1);
+}
+
+#[test]
+fn sample_keeps_playing_across_lines() {
+    // Silent for the first few bytes, so we can tell if it restarts
+    let mut pcm = vec![64u8; 8192];
+    pcm[0..4].fill(0);
+    let data = TestModule::new()
+        .set_sample(1, &pcm, 64)
+        .set_note(0, 0, 0, 1, 428, 0)
+        // Just a volume change, with no sample or period
+        .set_note(0, 2, 0, 0, 0, 0x0C20)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    assert_eq!(renderer.next_sample(), (0, 0));
+    for _ in 1..(2 * 960) {
+        let (left, _right) = renderer.next_sample();
+        assert!(left == 0 || left == 64 * 128);
+    }
+    assert_eq!(renderer.next_sample(), (64 * 64, 0));
+    assert_eq!(renderer.line(), 2);
+}
+
+#[test]
+fn very_low_sample_rate() {
+    // Rates below 50 Hz are played as 50 Hz, which is one sample per tick
+    let data = TestModule::new().build();
+    for rate in [0, 1, 49] {
+        let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+        let mut renderer = neotracker::Renderer::new(pt, rate);
+        for _ in 0..(64 * 6) {
+            assert_eq!(renderer.next_sample(), (0, 0));
+        }
+        assert_eq!(renderer.line(), 63);
+        renderer.next_sample();
+        assert!(renderer.finished());
+    }
+}
+
+#[test]
+fn advance_over_pattern_boundary() {
+    let data = TestModule::new().set_positions(&[0, 1]).build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    renderer.advance_lines(70);
+    assert_eq!((renderer.position(), renderer.line()), (1, 5));
+    assert!(!renderer.finished());
+    // the next line to play is the one after
+    renderer.next_sample();
+    assert_eq!((renderer.position(), renderer.line()), (1, 6));
+}
+
+#[test]
+fn advance_keeps_samples_playing() {
+    let ramp: Vec<u8> = (0..4096).map(|x| (x / 16) as u8).collect();
+    let data = TestModule::new()
+        .set_sample(1, &ramp, 64)
+        .set_note(0, 0, 0, 1, 428, 0)
+        .build();
+    // At 8000 Hz, there are 160 samples per tick, and 6 ticks per line
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut played = neotracker::Renderer::new(pt, 8000);
+    for _ in 0..(160 * 6 * 2) {
+        played.next_sample();
+    }
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut skipped = neotracker::Renderer::new(pt, 8000);
+    skipped.next_sample();
+    skipped.advance_lines(1);
+    for _ in 0..10 {
+        assert_eq!(played.next_sample(), skipped.next_sample());
+    }
+}
+
+#[test]
+fn advance_follows_pattern_break() {
+    let data = TestModule::new()
+        .set_positions(&[0, 1])
+        // Break to row 12 (given in decimal) of the next pattern
+        .set_note(0, 10, 0, 0, 0, 0x0D12)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    renderer.advance_lines(12);
+    assert_eq!((renderer.position(), renderer.line()), (1, 12));
+}
+
+#[test]
+fn advance_follows_position_jump() {
+    let data = TestModule::new()
+        .set_positions(&[0, 1, 2])
+        .set_note(0, 3, 0, 0, 0, 0x0B02)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    renderer.advance_lines(5);
+    assert_eq!((renderer.position(), renderer.line()), (2, 0));
+    // Run off the end of the song
+    renderer.advance_lines(64);
+    assert!(renderer.finished());
+}
+
+#[test]
+fn active_channels() {
+    let data = TestModule::new()
+        .set_sample(1, &[64; 256], 64)
+        .set_note(0, 0, 2, 1, 428, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    assert_eq!(renderer.active_channels(), 0);
+    renderer.next_sample();
+    assert_eq!(renderer.active_channels(), 1);
+}
+
+#[test]
+fn channel_effect() {
+    let data = TestModule::new()
+        .set_sample(1, &[64; 256], 64)
+        .set_note(0, 0, 1, 1, 428, 0x0105)
+        .set_note(0, 0, 2, 1, 428, 0x0C20)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    renderer.next_sample();
+    assert_eq!(renderer.channel_effect(0), None);
+    assert_eq!(
+        renderer.channel_effect(1),
+        Some(neotracker::Effect::SlideUp(5))
+    );
+    assert_eq!(renderer.channel_effect(2), None);
+    assert_eq!(renderer.channel_effect(4), None);
+}
+
+#[test]
+fn channel_sample_numbers() {
+    let data = TestModule::new()
+        .set_sample(1, &[64; 256], 64)
+        .set_sample(3, &[64; 256], 64)
+        .set_note(0, 0, 1, 3, 428, 0)
+        .set_note(0, 0, 3, 1, 428, 0)
+        .set_note(0, 1, 1, 1, 428, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    assert_eq!(renderer.channel_sample_numbers(), [0, 0, 0, 0]);
+    renderer.next_sample();
+    assert_eq!(renderer.channel_sample_numbers(), [0, 3, 0, 1]);
+    renderer.advance_lines(0);
+    renderer.next_sample();
+    assert_eq!(renderer.channel_sample_numbers(), [0, 1, 0, 1]);
+}
+
+#[test]
+fn slide_waits_for_second_tick() {
+    // At 8000 Hz, there are 160 samples per tick
+    let data = TestModule::new()
+        .set_sample(1, &[64; 4096], 64)
+        .set_note(0, 0, 0, 1, 428, 0x0110)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    // The whole of tick 0 plays the note as written
+    for _ in 0..160 {
+        renderer.next_sample();
+        assert_eq!(renderer.channel_period(0), Some(428));
+    }
+    // Then each tick slides it
+    renderer.next_sample();
+    assert_eq!(renderer.channel_period(0), Some(412));
+    assert_eq!(renderer.channel_period(4), None);
+    assert_eq!(
+        neotracker::Effect::SlideUp(16).timing(),
+        neotracker::EffectTiming::PerTick
+    );
+    assert_eq!(
+        neotracker::Effect::SetVolume(16).timing(),
+        neotracker::EffectTiming::OnLine
+    );
+}
+
+#[test]
+fn speed_multiplier() {
+    let data = TestModule::new().build();
+    let count = |multiplier| {
+        let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+        let mut renderer = neotracker::Renderer::new(pt, 8000);
+        renderer.set_speed_multiplier(multiplier);
+        let mut frames = 0;
+        while !renderer.finished() {
+            renderer.next_sample();
+            frames += 1;
+        }
+        frames
+    };
+    // 64 lines, 6 ticks per line, 160 samples per tick
+    let normal = count(1.0);
+    assert_eq!(normal, 64 * 6 * 160 + 1);
+    assert_eq!(count(2.0), 64 * 6 * 80 + 1);
+    assert_eq!(count(0.5), 64 * 6 * 320 + 1);
+    // Nonsense is ignored
+    assert_eq!(count(0.0), normal);
+    assert_eq!(count(f32::NAN), normal);
+}
+
+#[test]
+fn frames_remaining() {
+    // At 8000 Hz, there are 160 samples per tick, and 6 ticks per line
+    let data = TestModule::new()
+        .set_positions(&[0, 1])
+        // Double speed for the second pattern
+        .set_note(1, 0, 0, 0, 0, 0x0F03)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    assert_eq!(renderer.frames_remaining(), Some(64 * 960 + 64 * 480));
+    renderer.next_sample();
+    assert_eq!(renderer.frames_remaining(), Some(64 * 960 + 64 * 480 - 1));
+    renderer.advance_lines(125);
+    assert_eq!(renderer.frames_remaining(), Some(2 * 480));
+    for _ in 0..(2 * 480) {
+        renderer.next_sample();
+    }
+    assert_eq!(renderer.frames_remaining(), Some(0));
+    assert!(!renderer.finished());
+    renderer.next_sample();
+    assert!(renderer.finished());
+    assert_eq!(renderer.frames_remaining(), Some(0));
+}
+
+/// Render a whole song, and return the loudest sample on the left
+fn left_peak(renderer: &mut neotracker::Renderer) -> i16 {
+    let mut peak = 0;
+    while !renderer.finished() {
+        let (left, _right) = renderer.next_sample();
+        peak = peak.max(left.saturating_abs());
+    }
+    peak
+}
+
+#[test]
+fn headroom() {
+    // Two full-volume channels on the left
+    let data = TestModule::new()
+        .set_sample(1, &[127; 256], 64)
+        .set_note(0, 0, 0, 1, 428, 0)
+        .set_note(0, 0, 3, 1, 428, 0)
+        .build();
+
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    renderer.set_headroom(1);
+    // This clips
+    assert_eq!(left_peak(&mut renderer), i16::MAX);
+
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    // This doesn't
+    assert_eq!(left_peak(&mut renderer), 127 * 256);
+}
+
+#[test]
+fn set_volume_without_sample() {
+    let data = TestModule::new()
+        .set_sample(1, &[100; 256], 64)
+        .set_sample_loop(1, 0, 128)
+        .set_note(0, 0, 0, 1, 428, 0)
+        // No sample or note, so this changes the volume of the one playing
+        .set_note(0, 1, 0, 0, 0, 0x0C10)
+        // Anything over 64 is full volume
+        .set_note(0, 2, 0, 0, 0, 0x0C50)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    let (loud, _) = renderer.next_sample();
+    renderer.advance_lines(0);
+    let (quiet, _) = renderer.next_sample();
+    assert_eq!(renderer.line(), 1);
+    assert_ne!(quiet, 0);
+    assert_eq!(quiet * 4, loud);
+    renderer.advance_lines(0);
+    let (full, _) = renderer.next_sample();
+    assert_eq!(renderer.line(), 2);
+    assert_eq!(full, loud);
+}
+
+#[test]
+fn dither() {
+    // With this volume and headroom, the output is not a whole number
+    let data = TestModule::new()
+        .set_sample(1, &[100; 256], 32)
+        .set_sample_loop(1, 0, 128)
+        .set_note(0, 0, 0, 1, 428, 0)
+        .build();
+
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    renderer.set_headroom(3);
+    let plain: Vec<i16> = (0..1000).map(|_| renderer.next_sample().0).collect();
+    assert!(plain.iter().all(|s| *s == plain[0]));
+
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    renderer.set_headroom(3);
+    renderer.set_dither(true);
+    let dithered: Vec<i16> = (0..1000).map(|_| renderer.next_sample().0).collect();
+    // Some noise, but not much, and centred on the true value
+    let exact = 100.0 * 256.0 * 32.0 / 64.0 / 3.0;
+    assert!(dithered.iter().any(|s| *s != plain[0]));
+    assert!(dithered.iter().all(|s| (f64::from(*s) - exact).abs() < 2.0));
+    let mean = dithered.iter().map(|s| f64::from(*s)).sum::<f64>() / 1000.0;
+    assert!((mean - exact).abs() < 0.1);
+}
+
+#[test]
+fn note_delay() {
+    // At 8000 Hz, there are 160 samples per tick, and 6 ticks per line
+    let data = TestModule::new()
+        .set_sample(1, &[64; 4096], 64)
+        .set_sample(2, &[64; 4096], 16)
+        .set_note(0, 0, 0, 1, 428, 0)
+        // Play sample 2 on the third tick
+        .set_note(0, 1, 0, 2, 428, 0x0ED3)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    let left: Vec<i16> = (0..960 * 2).map(|_| renderer.next_sample().0).collect();
+    // Full volume, halved for headroom
+    assert_eq!(left[0], 64 * 256 / 2);
+    // The first note and its volume carry on into the second line...
+    assert_eq!(left[960], 64 * 256 / 2);
+    assert_eq!(left[960 + 479], 64 * 256 / 2);
+    // ...until the delayed note starts, with its own volume
+    assert_eq!(left[960 + 480], 16 * 256 / 2);
+}
+
+#[test]
+fn events() {
+    use neotracker::RenderEvent;
+    let data = TestModule::new().set_positions(&[0, 1]).build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    assert_eq!(renderer.poll_event(), None);
+
+    renderer.next_sample();
+    assert_eq!(
+        renderer.poll_event(),
+        Some(RenderEvent::PatternChanged {
+            position: 0,
+            pattern: 0
+        })
+    );
+    assert_eq!(
+        renderer.poll_event(),
+        Some(RenderEvent::LineChanged {
+            position: 0,
+            line: 0
+        })
+    );
+    assert_eq!(renderer.poll_event(), None);
+
+    let mut events = Vec::new();
+    while !renderer.finished() {
+        renderer.next_sample();
+        while let Some(event) = renderer.poll_event() {
+            events.push(event);
+        }
+    }
+    assert_eq!(events.len(), 63 + 1 + 64 + 1);
+    assert_eq!(
+        events[63],
+        RenderEvent::PatternChanged {
+            position: 1,
+            pattern: 1
+        }
+    );
+    assert_eq!(events.last(), Some(&RenderEvent::SongEnded));
+}
+
+#[test]
+fn events_overflow() {
+    use neotracker::RenderEvent;
+    let data = TestModule::new().build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    renderer.advance_lines(20);
+    // Only the most recent events are kept
+    let mut last = None;
+    let mut count = 0;
+    while let Some(event) = renderer.poll_event() {
+        last = Some(event);
+        count += 1;
+    }
+    assert_eq!(count, 8);
+    assert_eq!(
+        last,
+        Some(RenderEvent::LineChanged {
+            position: 0,
+            line: 19
+        })
+    );
+}
+
+#[test]
+fn antialias() {
+    let data = TestModule::new()
+        .set_sample(1, &[100; 256], 64)
+        .set_sample_loop(1, 0, 128)
+        // The highest note Pro Tracker can play
+        .set_note(0, 0, 0, 1, 113, 0)
+        // An octave below middle C
+        .set_note(0, 0, 1, 1, 856, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    assert_eq!(renderer.next_sample(), (12800, 12800));
+
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    renderer.set_antialias(true);
+    let (left, right) = renderer.next_sample();
+    // Stepping almost four bytes at a time, so turned down to about a quarter
+    assert!(left > 3000 && left < 3400, "{left}");
+    // Stepping about half a byte at a time, so left alone
+    assert_eq!(right, 12800);
+}
+
+#[test]
+fn fill() {
+    let data = TestModule::new()
+        .set_sample(1, &[127, 0x80, 64, 0], 64)
+        .set_note(0, 0, 0, 1, 428, 0)
+        .set_note(0, 0, 1, 1, 428, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt.clone(), 8000);
+    let mut expected = [0i16; 512];
+    renderer.fill(&mut expected);
+    assert!(expected.iter().any(|s| *s != 0));
+
+    let mut renderer = neotracker::Renderer::new(pt.clone(), 8000);
+    let mut floats = [0f32; 512];
+    renderer.fill(&mut floats);
+    assert!(floats.iter().all(|s| (-1.0..1.0).contains(s)));
+    for (f, i) in floats.iter().zip(expected.iter()) {
+        assert_eq!(*f, f32::from(*i) / 32768.0);
+    }
+
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    let mut bytes = [0i8; 512];
+    renderer.fill(&mut bytes);
+    for (b, i) in bytes.iter().zip(expected.iter()) {
+        assert_eq!(*b, (*i >> 8) as i8);
+    }
+}
+
+#[test]
+fn loop_past_sample_end() {
+    // The loop is bytes 2 to 6, but the sample is only 4 bytes long, so
+    // the loop is cut short to bytes 2 and 3
+    let data = TestModule::new()
+        .set_sample(1, &[1, 2, 3, 4], 64)
+        .set_sample_loop(1, 1, 2)
+        .set_note(0, 0, 0, 1, 428, 0)
+        .build();
+    let pt = neotracker::ProTrackerModule::new(&data).unwrap();
+    let mut renderer = neotracker::Renderer::new(pt, 8000);
+    for _ in 0..4 {
+        renderer.next_sample();
+    }
+    for _ in 0..900 {
+        let (left, _right) = renderer.next_sample();
+        assert!(left == 3 * 128 || left == 4 * 128);
+    }
+}